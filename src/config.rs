@@ -1,7 +1,10 @@
-use pest::iterators::Pairs;
+use crate::filter::{Condition, Filter, Operator};
+use crate::transform::{Explosion, Occurrence, Split, Transform};
+use pest::iterators::{Pair, Pairs};
 use pest::Parser;
 use pest_derive::Parser;
-use std::{collections::HashMap, hash::Hash};
+use regex::Regex;
+use std::collections::HashMap;
 
 pub const LEVEL: &str = "|";
 
@@ -9,101 +12,614 @@ pub const LEVEL: &str = "|";
 #[grammar = "parser.pest"] // path relative to src
 struct UnstructParser;
 
+/// The parsing rules extracted from a parser config file
+#[derive(Debug, Default)]
+pub struct Config {
+    /// Maps qualified xml names (name|level) to the columns they are output in
+    pub matcher: HashMap<String, Vec<String>>,
+    /// Maps levels to the filters that the elements on them must pass
+    pub filters: HashMap<usize, Vec<Filter>>,
+    /// The column names in the order they were specified
+    pub header: Vec<String>,
+    /// Maps qualified record elements (name|level) to the columns they contain
+    pub elements: HashMap<String, Vec<String>>,
+    /// The number of directives on each level
+    pub levels: Vec<usize>,
+    /// Maps column names to the transforms applied to their values
+    pub transforms: HashMap<String, Vec<Transform>>,
+    /// Maps column names to values that do not come from the xml
+    pub sources: HashMap<String, Source>,
+    /// Patterns with named groups, matched against file names, where each group is a column
+    pub file_patterns: Vec<Regex>,
+    /// Conditions on the columns of a row, which must all pass for the row to be output
+    pub wheres: Vec<Filter>,
+    /// Maps levels to the paths selected on them
+    pub selections: HashMap<usize, Vec<Selection>>,
+    /// Maps qualified record elements (name|level) that are given as paths to their paths
+    pub record_paths: HashMap<String, String>,
+    /// Maps qualified record elements (name|level) to the column holding the name of the element found
+    pub discriminators: HashMap<String, String>,
+    /// Maps qualified record elements (name|level) to the record element they are nested in
+    pub parents: HashMap<String, String>,
+    /// Maps column names to how their values are exploded into rows, and the column holding the index
+    pub explosions: HashMap<String, (Explosion, Option<String>)>,
+    /// Maps column names to the column whose value is split into them, how it is split, and which part
+    /// of how many they get
+    pub splits: HashMap<String, (String, Split, usize, usize)>,
+    /// Maps levels to the pivots on them
+    pub pivots: HashMap<usize, Vec<Pivot>>,
+    /// Maps namespace URIs to the prefixes they are given in the config, in place of those in the xml
+    pub namespaces: HashMap<String, String>,
+    /// Whether elements and attributes are matched by their names without prefixes, ignoring their namespaces
+    pub local_names: bool,
+    /// How names in the xml are matched against names in the config that are written differently
+    pub spellings: Spellings,
+}
+
+/// A directive whose value is selected by a path, or combined from several occurrences of an element
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selection {
+    pub path: String,
+    pub column: String,
+    pub occurrence: Occurrence,
+    /// The qualified record element (name|level) in whose block the directive is
+    pub element: String,
+}
+
+/// A directive that turns the child elements, or attributes, of the elements found by a path into rows of names and values
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pivot {
+    pub path: String,
+    pub name_column: String,
+    pub value_column: String,
+    /// The qualified record element (name|level) in whose block the directive is
+    pub element: String,
+}
+
+/// Names in the config, to which names in the xml are matched regardless of case or separators if
+/// `ignore case` or `ignore separators` is given
+#[derive(Debug, Default)]
+pub struct Spellings {
+    pub ignore_case: bool,
+    /// Whether `-` and `_` in names are ignored
+    pub ignore_separators: bool,
+    /// Maps normalized names to how they are written in the config
+    pub names: HashMap<String, String>,
+}
+
+/// A value for a column that does not come from the xml
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// A literal value, as in `const("SGW1")`
+    Const(String),
+    /// A value given on the command line, as in `param("batch_id")`
+    Param(String),
+    /// The value of an environment variable, as in `env("HOSTNAME")`
+    Env(String),
+}
+
+impl Spellings {
+    /// The name as it is compared, without the differences that are ignored
+    pub fn normalized(&self, name: &str) -> String {
+        let name = if self.ignore_separators { name.replace(['-', '_'], "") } else { name.to_owned() };
+        if self.ignore_case {
+            name.to_lowercase()
+        } else {
+            name
+        }
+    }
+
+    /// The name as it is written in the config, for a name in the xml that may be written differently
+    pub fn spelling(&self, name: &str) -> Option<&String> {
+        if self.names.is_empty() {
+            return None;
+        }
+        self.names.get(&self.normalized(name))
+    }
+
+    /// Adds the element and attribute names in a path, leaving out patterns, positions and functions
+    fn add(&mut self, path: &str) {
+        let names = path
+            .split('/')
+            .map(|step| step.split('[').next().unwrap_or_default().trim_start_matches('@'))
+            .filter(|step| !matches!(*step, "" | "." | "..") && !step.ends_with("()") && !is_pattern(step));
+        for name in names {
+            self.names.entry(self.normalized(name)).or_insert_with(|| name.to_owned());
+        }
+    }
+}
+
+/// Whether an xml name is a path that has to be selected, such as `a/b/c` or `//name`,
+/// rather than an element on the level, optionally followed by one of its attributes
+pub fn is_path(xml_name: &str) -> bool {
+    let (name, attribute) = match xml_name.split_once("/@") {
+        Some((name, attribute)) => (name, Some(attribute)),
+        None => (xml_name, None),
+    };
+    name.contains('/')
+        || name.starts_with('@')
+        || name.contains('[')
+        || name.ends_with("()")
+        || attribute == Some("*")
+        || matches!(name, "" | "." | ".." | "*")
+        || attribute.is_some_and(|attribute| attribute.contains('/'))
+}
+
+/// Whether an element name is a pattern, such as `*`, `sGW-*` or `sGW-GPRS-Ascii|pGW-GPRS-Ascii`
+pub fn is_pattern(name: &str) -> bool {
+    name.contains('*') || name.contains('|')
+}
+
+/// Matches an element name against a name or pattern, where `*` matches any characters
+/// and `|` separates alternatives
+pub fn matches_name(pattern: &str, name: &str) -> bool {
+    if !is_pattern(pattern) {
+        return pattern == name;
+    }
+    pattern.split('|').any(|alternative| {
+        let mut parts = alternative.split('*');
+        let first = parts.next().unwrap_or_default();
+        let Some(mut rest) = name.strip_prefix(first) else {
+            return false;
+        };
+        let mut parts = parts.peekable();
+        if parts.peek().is_none() {
+            return rest.is_empty();
+        }
+        while let Some(part) = parts.next() {
+            if parts.peek().is_none() {
+                return rest.ends_with(part);
+            }
+            match rest.find(part) {
+                Some(index) => rest = &rest[index + part.len()..],
+                None => return false,
+            }
+        }
+        true
+    })
+}
+
+/// The text of a quoted value, in which a quote is written as two quotes
+fn unquoted(value: Pair<Rule>) -> String {
+    value.as_str().replace("\"\"", "\"")
+}
+
+/// The value of a quoted string or a number
+fn literal(parsed: Pair<Rule>) -> String {
+    match parsed.into_inner().next() {
+        Some(value) if value.as_rule() == Rule::value => unquoted(value),
+        Some(number) => number.as_str().to_owned(),
+        None => String::default(),
+    }
+}
+
+fn condition(parsed: Pair<Rule>) -> Result<Condition, String> {
+    match parsed.as_rule() {
+        Rule::comparison => {
+            let mut inner = parsed.into_inner();
+            let operator = inner.next().unwrap().as_str();
+            let compared = inner.next().unwrap();
+            if compared.as_rule() == Rule::column_name {
+                return match operator {
+                    "~" | "!~" => Err(format!("Cannot match a regular expression with the column {}", compared.as_str())),
+                    _ => Ok(Condition::CompareColumn(Operator::new(operator), compared.as_str().to_owned())),
+                };
+            }
+            let value = literal(compared);
+            match operator {
+                "~" | "!~" => Regex::new(&value)
+                    .map(|regex| Condition::Matches(regex, operator == "!~"))
+                    .map_err(|error| format!("Invalid regular expression in filter: {}", error)),
+                _ => Ok(Condition::Compare(Operator::new(operator), value)),
+            }
+        }
+        Rule::membership => {
+            let mut negated = false;
+            let mut list: Vec<String> = Vec::default();
+            for item in parsed.into_inner() {
+                match item.as_rule() {
+                    Rule::negation => negated = true,
+                    _ => list.push(literal(item)),
+                }
+            }
+            Ok(Condition::In(list, negated))
+        }
+        _ if parsed.as_str() == "missing" => Ok(Condition::Missing),
+        _ => Ok(Condition::Exists),
+    }
+}
+
+/// Builds a filter from a disjunction, conjunction or negation, or a single test.
+/// Filters in brackets test xml names, while where clauses (`row`) test columns.
+fn filter(parsed: Pair<Rule>, row: bool) -> Result<Filter, String> {
+    match parsed.as_rule() {
+        Rule::disjunction | Rule::conjunction => {
+            let is_disjunction = parsed.as_rule() == Rule::disjunction;
+            let mut filters = parsed
+                .into_inner()
+                .filter(|pair| !matches!(pair.as_rule(), Rule::or_keyword | Rule::and_keyword))
+                .map(|pair| filter(pair, row))
+                .collect::<Result<Vec<Filter>, String>>()?;
+            Ok(if filters.len() == 1 {
+                filters.pop().unwrap()
+            } else if is_disjunction {
+                Filter::Or(filters)
+            } else {
+                Filter::And(filters)
+            })
+        }
+        Rule::negated => {
+            let mut inner = parsed.into_inner().peekable();
+            let negation = inner.next_if(|pair| pair.as_rule() == Rule::negation).is_some();
+            let inner = filter(inner.next().unwrap(), row)?;
+            Ok(if negation { Filter::Not(Box::new(inner)) } else { inner })
+        }
+        _ => {
+            let mut inner = parsed.into_inner().peekable();
+            let any = inner.next_if(|pair| pair.as_rule() == Rule::quantifier).is_some();
+            let name = inner.next().unwrap();
+            let condition = condition(inner.next().unwrap())?;
+            match (name.as_rule(), row) {
+                (Rule::xml_name, true) => {
+                    return Err(format!("A where clause tests columns, not the xml name \"{}\"", name.as_str()))
+                }
+                (Rule::column_name, false) => {
+                    return Err(format!("A filter tests xml names, which are quoted, not the column {}", name.as_str()))
+                }
+                _ => (),
+            }
+            if let (Condition::CompareColumn(_, column), false) = (&condition, row) {
+                return Err(format!("Only where clauses can compare with the column {}", column));
+            }
+            let name = name.as_str().to_owned();
+            Ok(if any { Filter::Any(name, condition) } else { Filter::Test(name, condition) })
+        }
+    }
+}
+
+/// Unwraps nested functions, pushing their transforms innermost first, and returns the xml name,
+/// or sets the source if the innermost function is one of const(), param() or env(), or the
+/// occurrence if it combines several values, such as last(), count() or sum()
+fn expression(
+    parsed: Pair<Rule>,
+    column_name: &str,
+    transforms: &mut Vec<Transform>,
+    source: &mut Option<Source>,
+    occurrence: &mut Option<Occurrence>,
+) -> String {
+    match parsed.as_rule() {
+        Rule::xml_name => parsed.as_str().to_owned(),
+        Rule::function => {
+            let mut inner = parsed.into_inner();
+            let function_name = inner.next().unwrap().as_str();
+            let argument = inner.next().unwrap();
+            if matches!(function_name, "const" | "param" | "env") {
+                if argument.as_rule() != Rule::xml_name || inner.next().is_some() {
+                    println!(
+                        "Could not parse the config file: The function {}() takes a single quoted value (for the column {})",
+                        function_name, column_name
+                    );
+                    std::process::exit(1);
+                }
+                let value = unquoted(argument);
+                *source = Some(match function_name {
+                    "const" => Source::Const(value),
+                    "param" => Source::Param(value),
+                    _ => Source::Env(value),
+                });
+                return String::default();
+            }
+            let is_xml_name = argument.as_rule() == Rule::xml_name;
+            let xml_name = expression(argument, column_name, transforms, source, occurrence);
+            let arguments: Vec<String> = inner.map(literal).collect();
+            if let Some(policy) = Occurrence::new(function_name, &arguments) {
+                match policy {
+                    Ok(policy) if is_xml_name => *occurrence = Some(policy),
+                    Ok(_) => {
+                        println!(
+                            "Could not parse the config file: The function {}() must be applied directly to a quoted value (for the column {})",
+                            function_name, column_name
+                        );
+                        std::process::exit(1);
+                    }
+                    Err(error) => {
+                        println!("Could not parse the config file: {} (for the column {})", error, column_name);
+                        std::process::exit(1);
+                    }
+                }
+                return xml_name;
+            }
+            match Transform::new(function_name, &arguments) {
+                Ok(transform) => transforms.push(transform),
+                Err(error) => {
+                    println!("Could not parse the config file: {} (for the column {})", error, column_name);
+                    std::process::exit(1);
+                }
+            }
+            xml_name
+        }
+        _ => {
+            println!("The directive is malformed: {:?}", parsed);
+            std::process::exit(1);
+        }
+    }
+}
+
 pub fn block_recurse(
     remainder: Pairs<Rule>,
-    matcher: &mut HashMap<String, String>,
-    filters: &mut HashMap<String, String>,
-    header: &mut Vec<String>,
-    elements: &mut HashMap<String, Vec<String>>,
-    levels: &mut Vec<usize>,
+    config: &mut Config,
     current_element: String,
     level: usize,
 ) {
-    let mut local_element = current_element;
-    // println!("Level: {}", level);
-    // println!("Remainder: {:?}", remainder);
-    while levels.len() < level {
-        levels.push(0);
+    let mut local_element = current_element.clone();
+    while config.levels.len() < level {
+        config.levels.push(0);
     }
     for parsed in remainder {
         match parsed.as_rule() {
             Rule::element => {
-                let element = {
-                    let element = parsed.as_str();
-                    let one_before_last = element.len() - 1;
-                    let trimmed = &element[1..one_before_last];
+                let mut inner = parsed.into_inner();
+                let trimmed = inner.next().unwrap().as_str().trim();
+                let element = if trimmed.contains('/') {
+                    // the record elements are found by the path, but recorded by their name
+                    let name = trimmed.rsplit('/').next().unwrap_or_default();
+                    if matches!(name, "" | "." | "..") || name.starts_with('@') {
+                        println!("Could not parse the config file: The path of <{}> must end with an element name", trimmed);
+                        std::process::exit(1);
+                    }
+                    let element = format!("{}{}{}", name, LEVEL, level);
+                    config.record_paths.insert(element.clone(), trimmed.to_owned());
+                    element
+                } else {
                     format!("{}{}{}", trimmed, LEVEL, level)
                 };
-                elements.insert(element.clone(), Vec::default());
+                let mut partial_header: Vec<String> = Vec::default();
+                if let Some(column_name) = inner.nth(1) {
+                    let column_name = column_name.as_str().to_owned();
+                    config.header.push(column_name.clone());
+                    partial_header.push(column_name.clone());
+                    config.discriminators.insert(element.clone(), column_name);
+                }
+                config.elements.insert(element.clone(), partial_header);
+                if !current_element.is_empty() {
+                    config.parents.insert(element.clone(), current_element.clone());
+                }
                 local_element = element;
             }
             Rule::directive => {
                 let mut column_name: Option<String> = None;
                 let mut xml_name: Option<String> = None;
+                let mut column_transforms: Vec<Transform> = Vec::default();
+                let mut source: Option<Source> = None;
+                let mut occurrence: Option<Occurrence> = None;
+                let mut explosion: Option<Explosion> = None;
+                let mut index_column: Option<String> = None;
+                let mut split_columns: Option<Vec<String>> = None;
+                let mut pivoted = false;
                 for column_or_xml in parsed.into_inner() {
                     match column_or_xml.as_rule() {
+                        Rule::columns => {
+                            // the value is held in a column that is not output, named after the columns it is split into
+                            column_name = Some(column_or_xml.as_str().split_whitespace().collect());
+                            split_columns = Some(column_or_xml.into_inner().map(|column| column.as_str().to_owned()).collect());
+                        }
+                        Rule::column_name if column_name.is_some() => {
+                            index_column = Some(column_or_xml.as_str().to_owned());
+                        }
                         Rule::column_name => {
                             column_name = Some(column_or_xml.as_str().to_owned());
                         }
-                        Rule::xml_name => {
-                            xml_name = Some(column_or_xml.as_str().to_owned());
-                        }
+                        Rule::as_keyword => (),
                         _ => {
-                            println!("The directive is malformed: {:?}", column_or_xml);
+                            let mut value = column_or_xml;
+                            // explode() splits the value after all other functions have been applied to it
+                            if value.as_rule() == Rule::function
+                                && value.clone().into_inner().next().unwrap().as_str() == "explode"
+                            {
+                                let mut inner = value.into_inner().skip(1);
+                                value = inner.next().unwrap();
+                                let arguments: Vec<String> = inner.map(literal).collect();
+                                match Explosion::new(&arguments) {
+                                    Ok(exploded) => explosion = Some(exploded),
+                                    Err(error) => {
+                                        println!(
+                                            "Could not parse the config file: {} (for the column {})",
+                                            error,
+                                            column_name.as_ref().unwrap()
+                                        );
+                                        std::process::exit(1);
+                                    }
+                                }
+                            }
+                            let is_pivot = value.as_rule() == Rule::function
+                                && value.clone().into_inner().next().unwrap().as_str() == "pivot";
+                            if let (true, Some(columns)) = (is_pivot, &split_columns) {
+                                let mut inner = value.into_inner().skip(1);
+                                let path = inner.next().unwrap();
+                                if columns.len() != 2 || path.as_rule() != Rule::xml_name || inner.next().is_some() {
+                                    println!(
+                                        "Could not parse the config file: The function pivot() takes a single quoted path and outputs a name and a value column (for the columns {})",
+                                        column_name.as_ref().unwrap()
+                                    );
+                                    std::process::exit(1);
+                                }
+                                config.pivots.entry(level).or_default().push(Pivot {
+                                    path: path.as_str().to_owned(),
+                                    name_column: columns[0].to_owned(),
+                                    value_column: columns[1].to_owned(),
+                                    element: local_element.to_owned(),
+                                });
+                                pivoted = true;
+                                continue;
+                            }
+                            if let Some(columns) = &split_columns {
+                                let function_name = match value.as_rule() {
+                                    Rule::function => value.clone().into_inner().next().unwrap().as_str(),
+                                    _ => "",
+                                };
+                                let mut inner = value.clone().into_inner().skip(1);
+                                let argument = inner.next();
+                                let arguments: Vec<String> = inner.map(literal).collect();
+                                match Split::new(function_name, &arguments, columns.len()) {
+                                    Some(Ok(split)) => {
+                                        value = argument.unwrap();
+                                        for (index, column) in columns.iter().enumerate() {
+                                            config.splits.insert(
+                                                column.to_owned(),
+                                                (column_name.as_ref().unwrap().to_owned(), split.clone(), index, columns.len()),
+                                            );
+                                        }
+                                    }
+                                    Some(Err(error)) => {
+                                        println!("Could not parse the config file: {} (for the columns {})", error, column_name.as_ref().unwrap());
+                                        std::process::exit(1);
+                                    }
+                                    None => {
+                                        println!(
+                                            "Could not parse the config file: Several columns must get their values from split() or capture() (for the columns {})",
+                                            column_name.as_ref().unwrap()
+                                        );
+                                        std::process::exit(1);
+                                    }
+                                }
+                            }
+                            xml_name = Some(expression(
+                                value,
+                                column_name.as_ref().unwrap(),
+                                &mut column_transforms,
+                                &mut source,
+                                &mut occurrence,
+                            ));
                         }
                     }
                 }
-                // println!("{} = {}", column_name.as_ref().unwrap(), xml_name.as_ref().unwrap());
-                matcher.insert(
-                    {
-                        let element = xml_name.as_ref().unwrap();
-                        format!("{}{}{}", element, LEVEL, level)
-                    },
-                    column_name.as_ref().unwrap().to_owned(),
-                );
-                header.push(column_name.as_ref().unwrap().to_owned());
-                levels[level-1] = levels[level-1] + 1;
-                if let Some(partial_header) = elements.get_mut(&local_element) {
-                    partial_header.push(column_name.as_ref().unwrap().to_owned());
+                if pivoted {
+                    let columns = split_columns.unwrap();
+                    config.header.extend(columns.clone());
+                    if let Some(partial_header) = config.elements.get_mut(&local_element) {
+                        partial_header.extend(columns);
+                    }
+                    continue;
+                }
+                let column_name = column_name.unwrap();
+                if index_column.is_some() && explosion.is_none() {
+                    println!(
+                        "Could not parse the config file: Only exploded values have an index column (for the column {})",
+                        column_name
+                    );
+                    std::process::exit(1);
+                }
+                if !column_transforms.is_empty() {
+                    config.transforms.insert(column_name.to_owned(), column_transforms);
+                }
+                if let Some(explosion) = explosion {
+                    config.explosions.insert(column_name.to_owned(), (explosion, index_column.clone()));
+                }
+                let mut columns = vec![column_name.to_owned()];
+                columns.extend(index_column);
+                let header_columns = split_columns.unwrap_or_else(|| columns.clone());
+                if let Some(source) = source {
+                    config.sources.insert(column_name.to_owned(), source);
+                    config.header.extend(header_columns);
+                    continue;
+                }
+                if occurrence.is_some() || is_path(xml_name.as_ref().unwrap()) {
+                    config.selections.entry(level).or_default().push(Selection {
+                        path: xml_name.unwrap(),
+                        column: column_name.to_owned(),
+                        occurrence: occurrence.unwrap_or(Occurrence::First),
+                        element: local_element.to_owned(),
+                    });
+                } else {
+                    config
+                        .matcher
+                        .entry({
+                            let element = xml_name.as_ref().unwrap();
+                            format!("{}{}{}", element, LEVEL, level)
+                        })
+                        .or_default()
+                        .push(column_name.to_owned());
+                    config.levels[level - 1] += 1;
+                }
+                config.header.extend(header_columns);
+                if let Some(partial_header) = config.elements.get_mut(&local_element) {
+                    partial_header.extend(columns);
                 }
             }
-            Rule::filter => {
-                let mut xml_name: Option<String> = None;
-                let mut value: Option<String> = None;
-                for xml_or_value in parsed.into_inner() {
-                    match xml_or_value.as_rule() {
-                        Rule::xml_name => {
-                            xml_name = Some(xml_or_value.as_str().to_owned());
-                        }
-                        Rule::value => {
-                            value = Some(xml_or_value.as_str().to_owned());
-                        }
-                        _ => {
-                            println!("The directive is malformed: {:?}", xml_or_value);
-                            std::process::exit(1);
-                        }
+            Rule::file_pattern => {
+                let pattern = unquoted(parsed.into_inner().next().unwrap());
+                match file_pattern(&pattern) {
+                    Ok((regex, columns)) => {
+                        config.header.extend(columns);
+                        config.file_patterns.push(regex);
+                    }
+                    Err(error) => {
+                        println!("Could not parse the config file: {}", error);
+                        std::process::exit(1);
                     }
                 }
-                filters.insert(
-                    {
-                        let element = xml_name.as_ref().unwrap();
-                        format!("{}{}{}", element, LEVEL, level)
-                    },
-                    value.as_ref().unwrap().to_owned(),
-                );
             }
+            Rule::namespace => {
+                // the default namespace is bound to the empty prefix, used by names without one
+                let mut prefix = String::default();
+                let mut uri = String::default();
+                for part in parsed.into_inner() {
+                    match part.as_rule() {
+                        Rule::prefix => prefix = part.as_str().to_owned(),
+                        Rule::value => uri = unquoted(part),
+                        _ => (),
+                    }
+                }
+                let described = |prefix: &str| match prefix {
+                    "" => "default namespace".to_owned(),
+                    prefix => format!("namespace {}", prefix),
+                };
+                let declared = config.namespaces.iter().find(|(other, other_prefix)| **other_prefix == prefix || **other == uri);
+                if let Some((other, other_prefix)) = declared.filter(|declared| *declared != (&uri, &prefix)) {
+                    println!(
+                        "Could not parse the config file: The {} cannot be bound to \"{}\", since the {} is bound to \"{}\"",
+                        described(&prefix),
+                        uri,
+                        described(other_prefix),
+                        other
+                    );
+                    std::process::exit(1);
+                }
+                config.namespaces.insert(uri, prefix);
+            }
+            Rule::option => {
+                let ignored = parsed.into_inner().nth(1).unwrap().as_str();
+                match ignored {
+                    "namespaces" => config.local_names = true,
+                    "case" => config.spellings.ignore_case = true,
+                    _ => config.spellings.ignore_separators = true,
+                }
+            }
+            Rule::filter => match filter(parsed.into_inner().next().unwrap(), false) {
+                Ok(filter) => {
+                    config.filters.entry(level).or_default().push(filter);
+                }
+                Err(error) => {
+                    println!("Could not parse the config file: {}", error);
+                    std::process::exit(1);
+                }
+            },
+            Rule::where_clause => match filter(parsed.into_inner().nth(1).unwrap(), true) {
+                Ok(filter) => config.wheres.push(filter),
+                Err(error) => {
+                    println!("Could not parse the config file: {}", error);
+                    std::process::exit(1);
+                }
+            },
             Rule::block => {
                 block_recurse(
                     parsed.into_inner(),
-                    matcher,
-                    filters,
-                    header,
-                    elements,
-                    levels,
+                    config,
                     local_element.to_owned(),
                     level + 1,
                 );
             }
+            Rule::EOI => (),
             _ => {
                 println!("No parsing rule matches: {:?}", parsed);
                 std::process::exit(1);
@@ -112,45 +628,157 @@ pub fn block_recurse(
     }
 }
 
-pub fn parse(
-    configuration: &str,
-) -> (
-    HashMap<String, String>,
-    HashMap<String, String>,
-    Vec<String>,
-    HashMap<String, Vec<String>>,
-    Vec<usize>,
-) {
-    // println!("The configuration is:\n{}", configuration);
+/// Compiles a pattern for file names and returns it together with the names of its groups
+pub fn file_pattern(pattern: &str) -> Result<(Regex, Vec<String>), String> {
+    let regex = Regex::new(pattern).map_err(|error| format!("Invalid file name pattern: {}", error))?;
+    let columns: Vec<String> = regex.capture_names().flatten().map(|name| name.to_owned()).collect();
+    if columns.is_empty() {
+        return Err(format!("The file name pattern has no named groups: {}", pattern));
+    }
+    Ok((regex, columns))
+}
+
+/// Parses a where clause given on the command line, such as `duration > 0 and servedIMSI exists`
+pub fn parse_where(expression: &str) -> Result<Filter, String> {
+    match UnstructParser::parse(Rule::where_expression, expression.trim()) {
+        Ok(mut parsed) => filter(parsed.next().unwrap(), true),
+        Err(error) => Err(format!("{}", error)),
+    }
+}
+
+pub fn parse(configuration: &str) -> Config {
     match UnstructParser::parse(Rule::config, configuration.trim()) {
         Result::Ok(mut remainder) => {
-            let mut matcher: HashMap<String, String> = HashMap::default();
-            let mut filters: HashMap<String, String> = HashMap::default();
-            let mut header: Vec<String> = Vec::default();
-            let mut elements: HashMap<String, Vec<String>> = HashMap::default();
-            let mut levels: Vec<usize> = Vec::default();
+            let mut config = Config::default();
             block_recurse(
                 remainder.next().unwrap().into_inner(),
-                &mut matcher,
-                &mut filters,
-                &mut header,
-                &mut elements,
-                &mut levels,
+                &mut config,
                 "".to_owned(),
                 1,
             );
-            /*  
-            println!("matcher: {:?}", &matcher);
-            println!("filters: {:?}", &filters);
-            println!("header: {:?}", &header);
-            println!("elements: {:?}", &elements);
-            println!("levels: {:?}", &levels);
-            */
-            (matcher, filters, header, elements, levels)        
+            if config.spellings.ignore_case || config.spellings.ignore_separators {
+                let name = |key: &String| key.rsplit_once(LEVEL).map(|(name, _)| name.to_owned()).unwrap_or_default();
+                let mut paths: Vec<String> = config.matcher.keys().chain(config.elements.keys()).map(name).collect();
+                paths.extend(config.record_paths.values().cloned());
+                paths.extend(config.selections.values().flatten().map(|selection| selection.path.to_owned()));
+                paths.extend(config.pivots.values().flatten().map(|pivot| pivot.path.to_owned()));
+                paths.extend(config.filters.values().flatten().flat_map(|filter| filter.names()).map(|name| name.to_owned()));
+                for path in paths {
+                    config.spellings.add(&path);
+                }
+            }
+            config
         }, 
         Result::Err(error) => {
-            println!("Could not parse the config file: {:?}", error);
+            println!("Could not parse the config file:\n{}", error);
             std::process::exit(1);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::apply_all;
+
+    #[test]
+    fn names_without_wildcards_match_exactly() {
+        assert!(matches_name("sGW-GPRS-Ascii", "sGW-GPRS-Ascii"));
+        assert!(!matches_name("sGW-GPRS-Ascii", "sGW-GPRS-Ascii2"));
+        assert!(!matches_name("sGW", "sgw"));
+    }
+
+    #[test]
+    fn wildcards_match_any_characters() {
+        assert!(matches_name("*", "anything"));
+        assert!(matches_name("*", ""));
+        assert!(matches_name("sGW-*", "sGW-GPRS-Ascii"));
+        assert!(matches_name("sGW-*", "sGW-"));
+        assert!(!matches_name("sGW-*", "pGW-GPRS-Ascii"));
+        assert!(matches_name("*-Ascii", "sGW-GPRS-Ascii"));
+        assert!(matches_name("*GPRS*", "sGW-GPRS-Ascii"));
+        assert!(matches_name("s*-*-A*i", "sGW-GPRS-Ascii"));
+        assert!(!matches_name("s*-*-A*i", "sGW-GPRS"));
+    }
+
+    #[test]
+    fn wildcards_do_not_reuse_characters() {
+        assert!(!matches_name("a*a", "a"));
+        assert!(matches_name("a*a", "aa"));
+        assert!(!matches_name("ab*ba", "aba"));
+        assert!(!matches_name("*ab*ab", "ab"));
+        assert!(matches_name("*ab*ab", "abab"));
+    }
+
+    #[test]
+    fn alternatives_match_any_of_them() {
+        assert!(matches_name("sGW-GPRS-Ascii|pGW-GPRS-Ascii", "pGW-GPRS-Ascii"));
+        assert!(matches_name("sGW-GPRS-Ascii|pGW-GPRS-Ascii", "sGW-GPRS-Ascii"));
+        assert!(!matches_name("sGW-GPRS-Ascii|pGW-GPRS-Ascii", "GPRS-Ascii"));
+        assert!(matches_name("sGW-*|pGW-*", "pGW-GPRS-Ascii"));
+        assert!(!matches_name("sGW-*|pGW-*", "mGW-GPRS-Ascii"));
+    }
+
+    #[test]
+    fn patterns_contain_wildcards_or_alternatives() {
+        assert!(is_pattern("*"));
+        assert!(is_pattern("a|b"));
+        assert!(!is_pattern("sGW-GPRS-Ascii"));
+    }
+
+    /// Evaluates a where clause on a row in which the columns a, b and c have the given values
+    fn passes(clause: &str, [a, b, c]: [&str; 3]) -> bool {
+        let filter = parse_where(clause).unwrap();
+        filter.passes_row(&|column| match column {
+            "a" => a,
+            "b" => b,
+            _ => c,
+        })
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let clause = r#"a = "1" or b = "1" and c = "1""#;
+        assert!(passes(clause, ["1", "0", "0"]));
+        assert!(passes(clause, ["0", "1", "1"]));
+        assert!(!passes(clause, ["0", "1", "0"]));
+    }
+
+    #[test]
+    fn not_applies_to_the_test_that_follows() {
+        let clause = r#"not a = "1" and b = "1""#;
+        assert!(passes(clause, ["0", "1", "0"]));
+        assert!(!passes(clause, ["1", "1", "0"]));
+        assert!(!passes(clause, ["0", "0", "0"]));
+        assert!(passes(r#"not not a = "1""#, ["1", "0", "0"]));
+    }
+
+    #[test]
+    fn parentheses_group_tests() {
+        let clause = r#"(a = "1" or b = "1") and c = "1""#;
+        assert!(!passes(clause, ["1", "0", "0"]));
+        assert!(passes(clause, ["0", "1", "1"]));
+        let negated = r#"not (a = "1" or b = "1") and c = "1""#;
+        assert!(passes(negated, ["0", "0", "1"]));
+        assert!(!passes(negated, ["0", "1", "1"]));
+        assert!(!passes(negated, ["0", "0", "0"]));
+    }
+
+    #[test]
+    fn invalid_where_clauses_are_errors() {
+        assert!(parse_where(r#"a = "1" or"#).is_err());
+        assert!(parse_where(r#"(a = "1""#).is_err());
+    }
+
+    #[test]
+    fn quotes_are_written_as_two_quotes() {
+        let config = parse(r#"
+            source = const("say ""hi""")
+            greeting = default("greeting", "say ""hi""")
+        "#);
+        assert_eq!(config.sources.get("source"), Some(&Source::Const(r#"say "hi""#.to_owned())));
+        assert_eq!(apply_all(&config.transforms["greeting"], ""), r#"say "hi""#);
+        let filter = parse_where(r#"greeting = "say ""hi""" and greeting in ("say ""hi""")"#).unwrap();
+        assert!(filter.passes_row(&|_| r#"say "hi""#));
+    }
+}
@@ -0,0 +1,272 @@
+use regex::Regex;
+use std::cmp::Ordering;
+
+/// An operator comparing a value found in the xml with a value given in the config
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// What a value found in the xml is tested against
+#[derive(Debug, Clone)]
+pub enum Condition {
+    Compare(Operator, String),
+    /// Compares with the value of another column, which is only possible in where clauses
+    CompareColumn(Operator, String),
+    /// The values in the list, or not in it if negated
+    In(Vec<String>, bool),
+    /// Matches the regex, or does not match it if negated
+    Matches(Regex, bool),
+    Exists,
+    Missing,
+}
+
+/// A filter on the elements or attributes of a level, such as `["xml_name" condition]` in the config,
+/// possibly combined with others using `and`, `or`, `not` and parentheses
+#[derive(Debug, Clone)]
+pub enum Filter {
+    Test(String, Condition),
+    /// A test that passes if any of the values matches, written as `[any "xml_name" condition]`
+    Any(String, Condition),
+    Not(Box<Filter>),
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+}
+
+impl Operator {
+    pub fn new(operator: &str) -> Operator {
+        match operator {
+            "!=" => Operator::NotEqual,
+            "<" => Operator::Less,
+            "<=" => Operator::LessOrEqual,
+            ">" => Operator::Greater,
+            ">=" => Operator::GreaterOrEqual,
+            _ => Operator::Equal,
+        }
+    }
+
+    /// Tests for equality as text, and otherwise compares the values in the order of `ordering`
+    pub fn compare(&self, value: &str, other: &str) -> bool {
+        match self {
+            Operator::Equal => value == other,
+            Operator::NotEqual => value != other,
+            Operator::Less => ordering(value, other).is_lt(),
+            Operator::LessOrEqual => ordering(value, other).is_le(),
+            Operator::Greater => ordering(value, other).is_gt(),
+            Operator::GreaterOrEqual => ordering(value, other).is_ge(),
+        }
+    }
+}
+
+/// Orders numerically if both values are numbers, and otherwise as text. Text, including an empty value, comes
+/// before numbers, so that values of mixed kinds, such as in `min()` and `max()`, have the same order whatever
+/// order they are found in.
+pub fn ordering(value: &str, other: &str) -> Ordering {
+    match (value.trim().parse::<f64>(), other.trim().parse::<f64>()) {
+        (Ok(value), Ok(other)) => value.total_cmp(&other),
+        (Ok(_), Err(_)) => Ordering::Greater,
+        (Err(_), Ok(_)) => Ordering::Less,
+        (Err(_), Err(_)) => value.cmp(other),
+    }
+}
+
+impl Condition {
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            Condition::Compare(operator, other) => operator.compare(value, other),
+            Condition::CompareColumn(_, _) => true,
+            Condition::In(list, negated) => list.iter().any(|other| other == value) != *negated,
+            Condition::Matches(regex, negated) => regex.is_match(value) != *negated,
+            Condition::Exists | Condition::Missing => true,
+        }
+    }
+
+    /// Tests the values of all elements or attributes with the name of the filter on a level.
+    /// Comparisons only apply to the values that are present, and every one of them must match.
+    pub fn passes(&self, values: &[&str]) -> bool {
+        match self {
+            Condition::Exists => !values.is_empty(),
+            Condition::Missing => values.is_empty(),
+            _ => values.iter().all(|value| self.matches(value)),
+        }
+    }
+}
+
+impl Filter {
+    /// Evaluates the filter, where `values` looks up the values of an element or attribute
+    pub fn passes<'a>(&self, values: &dyn Fn(&str) -> Vec<&'a str>) -> bool {
+        match self {
+            Filter::Test(xml_name, condition) => condition.passes(&values(xml_name)),
+            Filter::Any(xml_name, condition) => match condition {
+                Condition::Exists | Condition::Missing => condition.passes(&values(xml_name)),
+                _ => values(xml_name).iter().any(|value| condition.matches(value)),
+            },
+            Filter::Not(filter) => !filter.passes(values),
+            Filter::And(filters) => filters.iter().all(|filter| filter.passes(values)),
+            Filter::Or(filters) => filters.iter().any(|filter| filter.passes(values)),
+        }
+    }
+
+    /// Evaluates the filter as a where clause, where `value` looks up the value of a column in a row.
+    /// A column without a value is empty, and passes `missing` but not `exists`.
+    pub fn passes_row<'a>(&self, value: &dyn Fn(&str) -> &'a str) -> bool {
+        match self {
+            Filter::Test(column, condition) | Filter::Any(column, condition) => {
+                let column_value = value(column);
+                match condition {
+                    Condition::Exists => !column_value.is_empty(),
+                    Condition::Missing => column_value.is_empty(),
+                    Condition::CompareColumn(operator, other) => operator.compare(column_value, value(other)),
+                    _ => condition.matches(column_value),
+                }
+            }
+            Filter::Not(filter) => !filter.passes_row(value),
+            Filter::And(filters) => filters.iter().all(|filter| filter.passes_row(value)),
+            Filter::Or(filters) => filters.iter().any(|filter| filter.passes_row(value)),
+        }
+    }
+
+    /// The names of the elements, attributes or columns the filter refers to
+    pub fn names(&self) -> Vec<&str> {
+        match self {
+            Filter::Test(name, Condition::CompareColumn(_, other))
+            | Filter::Any(name, Condition::CompareColumn(_, other)) => vec![name.as_str(), other.as_str()],
+            Filter::Test(name, _) | Filter::Any(name, _) => vec![name.as_str()],
+            Filter::Not(filter) => filter.names(),
+            Filter::And(filters) | Filter::Or(filters) => {
+                filters.iter().flat_map(|filter| filter.names()).collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, negated: bool) -> Condition {
+        Condition::Matches(Regex::new(pattern).unwrap(), negated)
+    }
+
+    #[test]
+    fn numbers_are_compared_numerically_and_other_values_as_text() {
+        assert!(Operator::Less.compare("9", "10"));
+        assert!(Operator::Greater.compare(" 10 ", "9.5"));
+        assert!(Operator::GreaterOrEqual.compare("-1", "-1.0"));
+        assert!(Operator::Greater.compare("9", "10a"));
+        assert!(Operator::Less.compare("", "0"));
+        assert!(Operator::Less.compare("abc", "abd"));
+        assert!(Operator::LessOrEqual.compare("2022-05-06", "2022-05-06"));
+    }
+
+    #[test]
+    fn equality_compares_the_text() {
+        assert!(Operator::Equal.compare("seconds", "seconds"));
+        assert!(!Operator::Equal.compare("1.0", "1"));
+        assert!(Operator::NotEqual.compare("1.0", "1"));
+        assert!(!Operator::Less.compare("1.0", "1"));
+    }
+
+    #[test]
+    fn comparisons_pass_without_values() {
+        assert!(Condition::Compare(Operator::Equal, "seconds".to_owned()).passes(&[]));
+        assert!(Condition::In(vec!["100".to_owned()], false).passes(&[]));
+        assert!(matches("^[0-9]+$", false).passes(&[]));
+    }
+
+    #[test]
+    fn every_repeated_value_must_match() {
+        let condition = Condition::Compare(Operator::Greater, "0".to_owned());
+        assert!(condition.passes(&["1", "2"]));
+        assert!(!condition.passes(&["1", "0"]));
+    }
+
+    #[test]
+    fn existence_depends_on_whether_there_are_values() {
+        assert!(Condition::Exists.passes(&[""]));
+        assert!(!Condition::Exists.passes(&[]));
+        assert!(Condition::Missing.passes(&[]));
+        assert!(!Condition::Missing.passes(&["1"]));
+    }
+
+    #[test]
+    fn membership_may_be_negated() {
+        let list = vec!["100".to_owned(), "300".to_owned()];
+        assert!(Condition::In(list.clone(), false).passes(&["300"]));
+        assert!(!Condition::In(list.clone(), false).passes(&["200"]));
+        assert!(Condition::In(list.clone(), true).passes(&["200"]));
+        assert!(!Condition::In(list, true).passes(&["100", "200"]));
+    }
+
+    #[test]
+    fn regex_matches_may_be_negated() {
+        assert!(matches("^[0-9]+$", false).passes(&["123"]));
+        assert!(!matches("^[0-9]+$", false).passes(&["12a"]));
+        assert!(matches("^[0-9]+$", true).passes(&["12a"]));
+        assert!(!matches("^[0-9]+$", true).passes(&["123", "12a"]));
+    }
+
+    #[test]
+    fn any_needs_one_matching_value() {
+        let values = |name: &str| match name {
+            "volume" => vec!["0", "5"],
+            _ => vec![],
+        };
+        let positive = Condition::Compare(Operator::Greater, "0".to_owned());
+        assert!(!Filter::Test("volume".to_owned(), positive.clone()).passes(&values));
+        assert!(Filter::Any("volume".to_owned(), positive.clone()).passes(&values));
+        assert!(Filter::Test("missing".to_owned(), positive.clone()).passes(&values));
+        assert!(!Filter::Any("missing".to_owned(), positive).passes(&values));
+    }
+
+    /// A row in which the uplink and the limit are 10, the downlink 20 and the unit empty
+    fn row(column: &str) -> &'static str {
+        match column {
+            "uplink" => "10",
+            "downlink" => "20",
+            "limit" => "10",
+            _ => "",
+        }
+    }
+
+    #[test]
+    fn where_clauses_compare_columns_with_each_other() {
+        let compare = |column: &str, operator: &str, other: &str| {
+            Filter::Test(column.to_owned(), Condition::CompareColumn(Operator::new(operator), other.to_owned())).passes_row(&row)
+        };
+        assert!(compare("uplink", "<", "downlink"));
+        assert!(compare("uplink", "=", "limit"));
+        assert!(!compare("downlink", "<=", "limit"));
+        assert!(compare("unit", "<", "uplink"));
+    }
+
+    #[test]
+    fn empty_columns_are_missing_in_where_clauses() {
+        assert!(Filter::Test("unit".to_owned(), Condition::Missing).passes_row(&row));
+        assert!(!Filter::Test("unit".to_owned(), Condition::Exists).passes_row(&row));
+        assert!(Filter::Test("uplink".to_owned(), Condition::Exists).passes_row(&row));
+        assert!(!Filter::Any("uplink".to_owned(), Condition::Missing).passes_row(&row));
+    }
+
+    #[test]
+    fn empty_columns_are_compared_as_empty_text_in_where_clauses() {
+        assert!(!Filter::Test("unit".to_owned(), Condition::Compare(Operator::Equal, "seconds".to_owned())).passes_row(&row));
+        assert!(Filter::Test("unit".to_owned(), Condition::Compare(Operator::Equal, "".to_owned())).passes_row(&row));
+        assert!(Filter::Test("unit".to_owned(), matches("^[0-9]+$", true)).passes_row(&row));
+    }
+
+    #[test]
+    fn where_clauses_refer_to_both_compared_columns() {
+        let filter = Filter::Not(Box::new(Filter::And(vec![
+            Filter::Test("uplink".to_owned(), Condition::CompareColumn(Operator::Less, "downlink".to_owned())),
+            Filter::Test("unit".to_owned(), Condition::Exists),
+        ])));
+        assert_eq!(filter.names(), ["uplink", "downlink", "unit"]);
+        assert!(filter.passes_row(&row));
+    }
+}
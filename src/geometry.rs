@@ -0,0 +1,345 @@
+use roxmltree::Node;
+
+/// The coordinates of a sequence of positions, with `dimension` coordinates per position
+type Positions = Vec<String>;
+
+/// A GML geometry that can be output as WKT or WKB
+#[derive(Debug, Clone, PartialEq)]
+pub struct Geometry {
+    pub shape: Shape,
+    /// The number of coordinates of each position, 2 or 3
+    pub dimension: usize,
+    /// The EPSG code in the `srsName` of the geometry, if it has one
+    pub srid: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    Point(Positions),
+    LineString(Positions),
+    /// The exterior ring followed by the interior rings
+    Polygon(Vec<Positions>),
+    MultiSurface(Vec<Vec<Positions>>),
+}
+
+const GEOMETRIES: [&str; 4] = ["Point", "LineString", "Polygon", "MultiSurface"];
+
+impl Geometry {
+    /// Reads the first geometry that is the element or one of its descendants, such as a `gml:Polygon`
+    /// within `Objecten:geometrie`. Returns `None` if there is none or if its coordinates are invalid.
+    pub fn read(element: &Node) -> Option<Geometry> {
+        let geometry = element
+            .descendants()
+            .find(|el| el.is_element() && GEOMETRIES.contains(&el.tag_name().name()))?;
+        let dimension = dimension(&geometry)?;
+        let shape = match geometry.tag_name().name() {
+            "Point" => Shape::Point(positions(&geometry, dimension)?),
+            "LineString" => Shape::LineString(positions(&geometry, dimension)?),
+            "Polygon" => Shape::Polygon(rings(&geometry, dimension)?),
+            _ => Shape::MultiSurface(
+                geometry
+                    .descendants()
+                    .filter(|el| el.is_element() && el.tag_name().name() == "Polygon")
+                    .map(|polygon| rings(&polygon, dimension))
+                    .collect::<Option<_>>()?,
+            ),
+        };
+        let srid = geometry
+            .ancestors()
+            .find_map(|el| el.attribute("srsName"))
+            .and_then(srid);
+        Some(Geometry { shape, dimension, srid })
+    }
+
+    /// The geometry as WKT, prefixed by its SRID as in `SRID=28992;POLYGON (...)` if `extended` and it has one
+    pub fn wkt(&self, extended: bool) -> String {
+        let text = self.shape_text(if self.dimension == 3 { " Z" } else { "" });
+        match (extended, self.srid) {
+            (true, Some(srid)) => format!("SRID={};{}", srid, text),
+            _ => text,
+        }
+    }
+
+    /// The geometry as WKT without the `Z` tag, which SQL Server does not accept, so that Z coordinates
+    /// are only told apart by their number in each position
+    pub fn sql_server_wkt(&self) -> String {
+        self.shape_text("")
+    }
+
+    /// The WKT of the shape, where `z` follows the name of the type
+    fn shape_text(&self, z: &str) -> String {
+        match &self.shape {
+            Shape::Point(positions) => format!("POINT{} ({})", z, self.text(positions)),
+            Shape::LineString(positions) => format!("LINESTRING{} ({})", z, self.text(positions)),
+            Shape::Polygon(rings) => format!("POLYGON{} {}", z, self.polygon_text(rings)),
+            Shape::MultiSurface(polygons) => {
+                let polygons: Vec<String> = polygons.iter().map(|rings| self.polygon_text(rings)).collect();
+                format!("MULTIPOLYGON{} ({})", z, polygons.join(", "))
+            }
+        }
+    }
+
+    /// The geometry as little endian WKB in hex, or as EWKB including its SRID if `extended`
+    pub fn wkb(&self, extended: bool) -> String {
+        let mut bytes: Vec<u8> = Vec::default();
+        let srid = if extended { self.srid } else { None };
+        match &self.shape {
+            Shape::Point(positions) => {
+                self.wkb_header(&mut bytes, 1, extended, srid);
+                self.wkb_positions(&mut bytes, positions);
+            }
+            Shape::LineString(positions) => {
+                self.wkb_header(&mut bytes, 2, extended, srid);
+                bytes.extend(((positions.len() / self.dimension) as u32).to_le_bytes());
+                self.wkb_positions(&mut bytes, positions);
+            }
+            Shape::Polygon(rings) => self.wkb_polygon(&mut bytes, rings, extended, srid),
+            Shape::MultiSurface(polygons) => {
+                self.wkb_header(&mut bytes, 6, extended, srid);
+                bytes.extend((polygons.len() as u32).to_le_bytes());
+                for rings in polygons {
+                    self.wkb_polygon(&mut bytes, rings, extended, None);
+                }
+            }
+        }
+        bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+    }
+
+    fn text(&self, positions: &Positions) -> String {
+        let positions: Vec<String> = positions.chunks(self.dimension).map(|position| position.join(" ")).collect();
+        positions.join(", ")
+    }
+
+    fn polygon_text(&self, rings: &[Positions]) -> String {
+        let rings: Vec<String> = rings.iter().map(|ring| format!("({})", self.text(ring))).collect();
+        format!("({})", rings.join(", "))
+    }
+
+    /// The byte order and type, where ISO WKB adds 1000 to the type for Z coordinates while EWKB sets a flag,
+    /// followed by the SRID in EWKB
+    fn wkb_header(&self, bytes: &mut Vec<u8>, code: u32, extended: bool, srid: Option<u32>) {
+        let code = match (extended, self.dimension == 3) {
+            (false, true) => code + 1000,
+            (true, true) => code | 0x8000_0000,
+            _ => code,
+        };
+        bytes.push(1);
+        match srid {
+            Some(srid) => {
+                bytes.extend((code | 0x2000_0000).to_le_bytes());
+                bytes.extend(srid.to_le_bytes());
+            }
+            None => bytes.extend(code.to_le_bytes()),
+        }
+    }
+
+    fn wkb_polygon(&self, bytes: &mut Vec<u8>, rings: &[Positions], extended: bool, srid: Option<u32>) {
+        self.wkb_header(bytes, 3, extended, srid);
+        bytes.extend((rings.len() as u32).to_le_bytes());
+        for ring in rings {
+            bytes.extend(((ring.len() / self.dimension) as u32).to_le_bytes());
+            self.wkb_positions(bytes, ring);
+        }
+    }
+
+    fn wkb_positions(&self, bytes: &mut Vec<u8>, positions: &Positions) {
+        for coordinate in positions {
+            // the coordinates were checked to be numbers when read
+            bytes.extend(coordinate.parse::<f64>().unwrap_or(f64::NAN).to_le_bytes());
+        }
+    }
+}
+
+/// The `srsDimension` given on the coordinates, the geometry or an element containing it, or else the
+/// number of coordinates in the first position, as a geometry has the same dimension throughout
+fn dimension(geometry: &Node) -> Option<usize> {
+    let coordinates = geometry.descendants().find(|el| is_coordinates(el));
+    let dimension = match coordinates.and_then(|el| el.attribute("srsDimension")) {
+        Some(dimension) => Some(dimension),
+        None => geometry.ancestors().find_map(|el| el.attribute("srsDimension")),
+    };
+    let dimension = match (dimension, coordinates) {
+        (Some(dimension), _) => dimension.trim().parse::<usize>().ok()?,
+        (None, Some(el)) if el.tag_name().name() == "pos" => el.text().unwrap_or("").split_whitespace().count(),
+        (None, Some(el)) if el.tag_name().name() == "coordinates" => {
+            let first = el.text().unwrap_or("").split_whitespace().next().unwrap_or("");
+            first.split(',').count()
+        }
+        _ => 2,
+    };
+    (2..=3).contains(&dimension).then_some(dimension)
+}
+
+fn is_coordinates(element: &Node) -> bool {
+    element.is_element() && matches!(element.tag_name().name(), "posList" | "pos" | "coordinates")
+}
+
+/// The coordinates of the positions in `gml:posList`, `gml:pos` or the older `gml:coordinates`
+/// within the element, which must all be numbers and make up whole positions
+fn positions(element: &Node, dimension: usize) -> Option<Positions> {
+    let positions: Positions = element
+        .descendants()
+        .filter(is_coordinates)
+        .flat_map(|el| {
+            let separators = if el.tag_name().name() == "coordinates" { "," } else { "" };
+            el.text()
+                .unwrap_or("")
+                .split(|c: char| c.is_whitespace() || separators.contains(c))
+                .filter(|coordinate| !coordinate.is_empty())
+                .map(|coordinate| coordinate.to_owned())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    let numbers = positions.iter().all(|coordinate| coordinate.parse::<f64>().is_ok());
+    (numbers && !positions.is_empty() && positions.len().is_multiple_of(dimension)).then_some(positions)
+}
+
+/// The rings of a polygon, given as `gml:exterior` and `gml:interior`, or as `gml:outerBoundaryIs`
+/// and `gml:innerBoundaryIs` in older GML, where the exterior comes first
+fn rings(polygon: &Node, dimension: usize) -> Option<Vec<Positions>> {
+    let rings: Vec<Positions> = polygon
+        .descendants()
+        .filter(|el| el.is_element() && el.tag_name().name() == "LinearRing")
+        .map(|ring| positions(&ring, dimension))
+        .collect::<Option<_>>()?;
+    (!rings.is_empty()).then_some(rings)
+}
+
+/// The EPSG code that ends an `srsName`, such as `urn:ogc:def:crs:EPSG::28992`, `EPSG:28992`,
+/// `http://www.opengis.net/def/crs/EPSG/0/28992` or `http://www.opengis.net/gml/srs/epsg.xml#28992`.
+/// Codes of other authorities, such as `urn:ogc:def:crs:OGC:1.3:CRS84`, are not SRIDs.
+fn srid(srs_name: &str) -> Option<u32> {
+    let lower = srs_name.to_ascii_lowercase();
+    let (_, code) = lower.rsplit_once("epsg")?;
+    let start = code.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    // the separator may hold a version, as in EPSG:6.6:28992 or EPSG/0/28992
+    let version = |separator: &str| separator.chars().all(|c| c.is_ascii_digit() || c == '.');
+    let separator = &code[..start];
+    let between = |open: char, close: char| {
+        separator.len() >= 2
+            && separator.starts_with(open)
+            && separator.ends_with(close)
+            && version(&separator[1..separator.len() - 1])
+    };
+    let valid = matches!(separator, ":" | ".xml#") || between(':', ':') || between('/', '/');
+    if valid {
+        code[start..].parse::<u32>().ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn geometry(xml: &str) -> Option<Geometry> {
+        let xml = format!(r#"<geometrie xmlns:gml="http://www.opengis.net/gml/3.2">{}</geometrie>"#, xml);
+        let doc = roxmltree::Document::parse(&xml).unwrap();
+        Geometry::read(&doc.root_element())
+    }
+
+    #[test]
+    fn srid_is_only_taken_from_epsg_codes() {
+        assert_eq!(srid("urn:ogc:def:crs:EPSG::28992"), Some(28992));
+        assert_eq!(srid("urn:ogc:def:crs:EPSG:6.6:28992"), Some(28992));
+        assert_eq!(srid("EPSG:4326"), Some(4326));
+        assert_eq!(srid("http://www.opengis.net/def/crs/EPSG/0/28992"), Some(28992));
+        assert_eq!(srid("http://www.opengis.net/gml/srs/epsg.xml#28992"), Some(28992));
+        assert_eq!(srid("urn:ogc:def:crs:OGC:1.3:CRS84"), None);
+        assert_eq!(srid("EPSG:"), None);
+        assert_eq!(srid("EPSG28992"), None);
+        assert_eq!(srid("28992"), None);
+    }
+
+    #[test]
+    fn point() {
+        let point = geometry(r#"<gml:Point srsName="EPSG:4326"><gml:pos>5.1 52.0</gml:pos></gml:Point>"#).unwrap();
+        assert_eq!(point.wkt(false), "POINT (5.1 52.0)");
+        assert_eq!(point.wkt(true), "SRID=4326;POINT (5.1 52.0)");
+        assert_eq!(point.wkb(false), "010100000066666666666614400000000000004A40");
+        // the SRID flag is set in the type, which is followed by the SRID 4326
+        assert_eq!(point.wkb(true), "0101000020E610000066666666666614400000000000004A40");
+    }
+
+    #[test]
+    fn point_without_epsg_code_has_no_srid() {
+        let point = geometry(r#"<gml:Point srsName="urn:ogc:def:crs:OGC:1.3:CRS84"><gml:pos>5 52</gml:pos></gml:Point>"#).unwrap();
+        assert_eq!(point.srid, None);
+        assert_eq!(point.wkt(true), "POINT (5 52)");
+        assert_eq!(point.wkb(true), point.wkb(false));
+    }
+
+    #[test]
+    fn line_string() {
+        let line = geometry(r#"<gml:LineString><gml:posList>1 2 3 4</gml:posList></gml:LineString>"#).unwrap();
+        assert_eq!(line.wkt(false), "LINESTRING (1 2, 3 4)");
+        assert_eq!(line.sql_server_wkt(), line.wkt(false));
+        assert_eq!(
+            line.wkb(false),
+            "010200000002000000000000000000F03F000000000000004000000000000008400000000000001040"
+        );
+    }
+
+    #[test]
+    fn polygon_with_z_and_interior_ring() {
+        let polygon = geometry(
+            r#"<gml:Polygon srsName="urn:ogc:def:crs:EPSG::28992" srsDimension="3">
+                <gml:exterior><gml:LinearRing><gml:posList>0 0 0 4 0 0 4 4 0 0 0 0</gml:posList></gml:LinearRing></gml:exterior>
+                <gml:interior><gml:LinearRing><gml:posList>1 1 0 2 1 0 2 2 0 1 1 0</gml:posList></gml:LinearRing></gml:interior>
+            </gml:Polygon>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            polygon.wkt(true),
+            "SRID=28992;POLYGON Z ((0 0 0, 4 0 0, 4 4 0, 0 0 0), (1 1 0, 2 1 0, 2 2 0, 1 1 0))"
+        );
+        assert_eq!(
+            polygon.sql_server_wkt(),
+            "POLYGON ((0 0 0, 4 0 0, 4 4 0, 0 0 0), (1 1 0, 2 1 0, 2 2 0, 1 1 0))"
+        );
+        // ISO WKB adds 1000 to the type for Z, while EWKB sets the Z and SRID flags
+        assert!(polygon.wkb(false).starts_with("01EB0300000200000004000000"));
+        assert!(polygon.wkb(true).starts_with("01030000A0407100000200000004000000"));
+        assert_eq!(polygon.wkb(false).len(), (1 + 4 + 4 + 2 * (4 + 4 * 3 * 8)) * 2);
+    }
+
+    #[test]
+    fn multi_surface_with_srid_on_the_container() {
+        let multi = geometry(
+            r#"<gml:MultiSurface srsName="EPSG:28992">
+                <gml:surfaceMember><gml:Polygon><gml:exterior><gml:LinearRing>
+                    <gml:pos>0 0</gml:pos><gml:pos>1 0</gml:pos><gml:pos>1 1</gml:pos><gml:pos>0 0</gml:pos>
+                </gml:LinearRing></gml:exterior></gml:Polygon></gml:surfaceMember>
+                <gml:surfaceMember><gml:Polygon><gml:exterior><gml:LinearRing>
+                    <gml:posList>5 5 6 5 6 6 5 5</gml:posList>
+                </gml:LinearRing></gml:exterior></gml:Polygon></gml:surfaceMember>
+            </gml:MultiSurface>"#,
+        )
+        .unwrap();
+        assert_eq!(multi.wkt(false), "MULTIPOLYGON (((0 0, 1 0, 1 1, 0 0)), ((5 5, 6 5, 6 6, 5 5)))");
+        // only the collection has the SRID, and each polygon has a header of its own
+        let wkb = multi.wkb(true);
+        assert!(wkb.starts_with("0106000020407100000200000001030000000100000004000000"));
+    }
+
+    #[test]
+    fn old_coordinates_infer_the_dimension() {
+        let polygon = geometry(
+            r#"<gml:Polygon><gml:outerBoundaryIs><gml:LinearRing>
+                <gml:coordinates>0,0,1 1,0,1 1,1,1 0,0,1</gml:coordinates>
+            </gml:LinearRing></gml:outerBoundaryIs></gml:Polygon>"#,
+        )
+        .unwrap();
+        assert_eq!(polygon.dimension, 3);
+        assert_eq!(polygon.wkt(false), "POLYGON Z ((0 0 1, 1 0 1, 1 1 1, 0 0 1))");
+    }
+
+    #[test]
+    fn invalid_coordinates_give_no_geometry() {
+        assert_eq!(geometry(r#"<gml:Point><gml:pos>x y</gml:pos></gml:Point>"#), None);
+        assert_eq!(geometry(r#"<gml:LineString><gml:posList>1 2 3</gml:posList></gml:LineString>"#), None);
+        assert_eq!(geometry(r#"<gml:Point srsDimension="4"><gml:pos>1 2 3 4</gml:pos></gml:Point>"#), None);
+        assert_eq!(geometry(r#"<other>1 2</other>"#), None);
+    }
+}
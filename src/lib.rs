@@ -1 +1,4 @@
 pub mod config;
+pub mod filter;
+pub mod geometry;
+pub mod transform;
@@ -1,12 +1,19 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use glob::glob;
-use roxmltree::{self, Node};
-use std::collections::{HashMap, HashSet};
+use roxmltree::{self, Attribute, Node};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
 use std::fs::{read_to_string, File};
 use std::io::Write;
 use std::rc::Rc;
-use unstruct::config::{parse, LEVEL};
+use std::time::{SystemTime, UNIX_EPOCH};
+use unstruct::config::{
+    file_pattern, is_pattern, matches_name, parse, parse_where, Config, Pivot, Selection, Source, Spellings, LEVEL,
+};
+use unstruct::filter::Filter;
+use unstruct::geometry::Geometry;
+use unstruct::transform::{apply_all, json_string, utc_timestamp, Occurrence};
 
 /// Unstruct is a program that parses simple xml files into text files,
 /// suitable for bulk inserts into a relational database
@@ -25,18 +32,98 @@ struct Args {
     #[clap(short, long, default_value = "unstruct.parser")]
     parser: String,
 
-    /// Add metadata columns and values in the output file
+    /// Add metadata columns and values in the output file, given as a comma separated list
+    #[clap(short, long, value_enum, value_delimiter = ',', num_args = 0..=1, default_missing_value = "path")]
+    metadata: Vec<Metadata>,
+
+    /// The batch id output in the _batch metadata column [default: the start time of the run]
     #[clap(short, long)]
-    metadata: bool,
+    batch: Option<String>,
 
     /// Do not write any info to output
     #[clap(short, long)]
     quiet: bool,
+
+    /// A pattern with named groups matched against file names, where each group becomes a column
+    #[clap(short, long)]
+    captures: Option<String>,
+
+    /// A value for param("key") columns in the config, given as key=value (can be repeated)
+    #[clap(long = "param")]
+    params: Vec<String>,
+
+    /// Only output rows whose columns pass the condition, such as "duration > 0" (can be repeated)
+    #[clap(short, long = "where")]
+    wheres: Vec<String>,
+}
+
+/// The metadata columns that can be added to the output
+#[derive(ValueEnum, Clone, Debug, PartialEq)]
+enum Metadata {
+    /// The path of the parsed file, without the part common to all parsed files
+    Path,
+    /// The absolute path of the parsed file
+    AbsolutePath,
+    /// The size of the parsed file in bytes
+    Size,
+    /// The time the parsed file was last modified
+    Modified,
+    /// The SHA-256 hash of the contents of the parsed file
+    Hash,
+    /// The number of the row within the parsed file
+    Row,
+    /// The position of the record element among its siblings with the same name
+    Ordinal,
+    /// The line in the parsed file where the record element starts
+    Line,
+    /// The column in the parsed file where the record element starts
+    Column,
+    /// The byte offset in the parsed file where the record element starts
+    Offset,
+    /// The time the run started
+    Started,
+    /// The batch id of the run
+    Batch,
+}
+
+impl Metadata {
+    fn column(&self) -> &'static str {
+        match self {
+            Metadata::Path => "_path",
+            Metadata::AbsolutePath => "_absolute_path",
+            Metadata::Size => "_size",
+            Metadata::Modified => "_modified",
+            Metadata::Hash => "_hash",
+            Metadata::Row => "_row",
+            Metadata::Ordinal => "_ordinal",
+            Metadata::Line => "_line",
+            Metadata::Column => "_column",
+            Metadata::Offset => "_offset",
+            Metadata::Started => "_started",
+            Metadata::Batch => "_batch",
+        }
+    }
+}
+
+/// The output file and the number of rows written for the file being parsed
+struct Output {
+    file: File,
+    rows: usize,
+}
+
+/// The values found while traversing a file, and the output that their rows are written to
+struct State {
+    /// Maps qualified record elements (name|level) to the columns that got values since the last row
+    parsed: HashMap<String, HashSet<String>>,
+    result: HashMap<String, Match>,
+    output: Output,
 }
 
 #[derive(Debug)]
 enum Match {
     Value(String),
+    /// Several values, each of which is output in a row of its own
+    Values(Vec<String>),
     Nothing,
 }
 
@@ -45,87 +132,77 @@ const TERMINATOR: char = '\n';
 
 fn traverse(
     nodes: Vec<Rc<Node>>,
-    matcher: &HashMap<String, String>,
-    filters: &HashMap<String, String>,
-    header: &Vec<String>,
-    elements: &HashMap<String, Vec<String>>,
-    levels: &Vec<usize>,
+    config: &Config,
     namespaces: &HashMap<String, String>,
-    parsed: &mut HashMap<String, HashSet<String>>,
-    result: &mut HashMap<String, Match>,
-    output: &mut File,
+    state: &mut State,
     recording: Option<String>,
     siblings: bool,
     depth: usize,
 ) {
-    // let current_element = recording.clone();
-    // println!("Parsing depth: {}", depth);
+    let Config {
+        wheres,
+        header,
+        elements,
+        record_paths,
+        discriminators,
+        parents,
+        explosions,
+        splits,
+        levels,
+        transforms,
+        spellings,
+        ..
+    } = config;
     if depth > levels.len() {
         return;
     }
     let mut siblings = siblings;
     let mut found: usize = 0;
-    let mut skip: bool;
+    let foreign = recording
+        .as_ref()
+        .map(|recording| foreign_columns(recording, elements, parents))
+        .unwrap_or_default();
     if siblings {
         for element in nodes.into_iter().filter(|el| el.is_element()) {
-            skip = false;
             let mut nodes_to_search = Vec::default();
             if element.has_children() {
                 siblings = false;
                 nodes_to_search.extend(element.children().map(Rc::new));
             }  
-            if recording.is_some() {
-                let mut xml_name = element.tag_name().name().to_string();
-                let schema_name = element.tag_name().namespace();
-                if schema_name.is_some() {
-                    let namespace = namespaces.get(schema_name.unwrap());
-                    if namespace.is_some() {
-                        xml_name = format!("{}:{}", namespace.unwrap(), xml_name);
-                    }
+            if let Some(recording) = &recording {
+                if rejected(&[Rc::clone(&element)], config, namespaces, true, depth) {
+                    continue;
                 }
-                let qualified_element_name = format!("{}{}{}", xml_name, LEVEL, depth);
-                if let Some(partial_header) = elements.get(&qualified_element_name) {
-                    result.extend(
+                let xml_name = qualified_name(&element, namespaces, spellings);
+                // values of other record elements, except the ones this record is nested in, must not end up in its rows
+                let nested_in = ancestry(recording, parents);
+                for (_, partial_header) in elements.iter().filter(|(key, _)| !nested_in[1..].contains(&key.as_str())) {
+                    state.result.extend(
                         partial_header
                             .iter()
                             .map(|head| (head.to_owned(), Match::Nothing)),
                     );
                 }
-                let mut xml_value = element.text().unwrap_or("").to_owned();
-                let qualified_name = format!("{}{}{}", xml_name, LEVEL, depth);
-                let value_filter = filters.get(&qualified_name);
-                if value_filter.is_some() && xml_value.ne(value_filter.unwrap()) {
-                    skip = true;
+                locate(&element, &mut state.result);
+                if let Some(column) = discriminators.get(recording) {
+                    state.result.insert(column.to_owned(), Match::Value(xml_name.to_owned()));
+                    state.parsed.entry(recording.to_owned()).or_default().insert(column.to_owned());
                 }
-                //println!("Filtering: {} = {:?} (siblings = {})", &qualified_name, filters.get(&qualified_name), siblings);
-                found = found + record(&xml_name, &xml_value, recording.as_ref().unwrap(), matcher, parsed, result, depth);
+                let xml_value = element.text().unwrap_or("");
+                record(&xml_name, xml_value, recording, config, &foreign, state, depth);
                 for attribute in element.attributes() {
-                    let xml_attribute = format!("{}{}{}", xml_name, "/@", attribute.name());
-                    xml_value = attribute.value().to_owned();
-                    let qualified_name = format!("{}{}{}", xml_attribute, LEVEL, depth);
-                    let value_filter = filters.get(&qualified_name);
-                    if value_filter.is_some() && xml_value.ne(value_filter.unwrap()) {
-                        skip = true;
-                        break;
-                    }
-                    //println!("Filtering: {} = {:?} (siblings = {})", &qualified_name, filters.get(&qualified_name), siblings);
-                    //println!("Attribute found: {} (on {} with {} found)", xml_value, recording.as_ref().unwrap(), found);
-                    found = found + record(&xml_attribute, &xml_value, recording.as_ref().unwrap(), matcher, parsed, result, depth);
+                    let xml_attribute = format!("{}{}{}", xml_name, "/@", qualified_attribute_name(attribute, namespaces, spellings));
+                    record(&xml_attribute, attribute.value(), recording, config, &foreign, state, depth);
                 }
-                // println!("Number found: {}", found);
+                let record_element = [Rc::clone(&element)];
+                record_selections(&record_element, config, namespaces, recording, state, true, depth);
             } 
-            if !nodes_to_search.is_empty() && !skip {
+            if !nodes_to_search.is_empty() {
                 traverse(
                     nodes_to_search,
-                    matcher,
-                    filters,
-                    header,
-                    elements,
-                    levels,
+                    config,
                     namespaces,
-                    parsed,
-                    result,
-                    output,
+                    state,
                     recording.to_owned(),
                     siblings,
                     if siblings { depth } else { depth + 1 },
@@ -134,34 +211,34 @@ fn traverse(
         }
     } else {
         let mut nodes_to_search = Vec::default();
-        skip = false;
-        for element in nodes.into_iter().filter(|el| el.is_element()) {
-            let mut xml_name = element.tag_name().name().to_string();
-            let schema_name = element.tag_name().namespace();
-            if schema_name.is_some() {
-                let namespace = namespaces.get(schema_name.unwrap());
-                if namespace.is_some() {
-                    xml_name = format!("{}:{}", namespace.unwrap(), xml_name);
-                }
+        let skip = recording.is_some() && rejected(&nodes, config, namespaces, false, depth);
+        if skip {
+            // values of records on a rejected level must not linger in rows of the records above
+            let level = format!("{}{}", LEVEL, depth);
+            for (_, partial_header) in elements.iter().filter(|(key, _)| key.ends_with(&level)) {
+                state.result.extend(partial_header.iter().map(|head| (head.to_owned(), Match::Nothing)));
             }
-            let qualified_element_name = format!("{}{}{}", xml_name, LEVEL, depth);
-            if elements.contains_key(&qualified_element_name) {
-                // println!("Element to record: {}", qualified_element_name);
-                let mut siblings_to_search = Vec::default();
-                siblings_to_search.extend(element.next_siblings().filter(|el| el.has_tag_name(element.tag_name())).map(Rc::new));
-                if !siblings_to_search.is_empty() {
+        }
+        if let Some(recording) = recording.as_ref().filter(|_| !skip) {
+            record_selections(&nodes, config, namespaces, recording, state, false, depth);
+        }
+        let level = format!("{}{}", LEVEL, depth);
+        for (index, element) in nodes.iter().enumerate().filter(|(_, el)| el.is_element() && !skip) {
+            let xml_name = qualified_name(element, namespaces, spellings);
+            // the records on the level are found from the first element that is one, or leads to one given as a path
+            let leads_to_record = find_record_element(&xml_name, elements, record_paths, depth).is_some()
+                || record_paths.iter().any(|(record_element, path)| {
+                    record_element.ends_with(&level)
+                        && !select_elements(&nodes[index..=index], path, namespaces, spellings).is_empty()
+                });
+            if leads_to_record {
+                for (record_element, record) in find_records(&nodes[index..], config, namespaces, depth) {
                     traverse(
-                        siblings_to_search,
-                        matcher,
-                        filters,
-                        header,
-                        elements,
-                        levels,
+                        vec![Rc::new(record)],
+                        config,
                         namespaces,
-                        parsed,
-                        result,
-                        output,
-                        Some(qualified_element_name),
+                        state,
+                        Some(record_element),
                         true,
                         depth,
                     );
@@ -170,70 +247,531 @@ fn traverse(
             } else if element.has_children() {
                 nodes_to_search.extend(element.children().map(Rc::new));
             }  
-            if recording.is_some() && found < levels[depth-1] {
-                let mut xml_value = element.text().unwrap_or("").to_owned();
-                let qualified_name = format!("{}{}{}", xml_name, LEVEL, depth);
-                let value_filter = filters.get(&qualified_name);
-                if value_filter.is_some() && xml_value.ne(value_filter.unwrap()) {
-                    skip = true;
-                    break;
-                }
-                // println!("Filtering: {} = {:?} (siblings = {})", &qualified_name, filters.get(&qualified_name), siblings);
-                found = found + record(&xml_name, &xml_value, recording.as_ref().unwrap(), matcher, parsed, result, depth);
+            if let Some(recording) = recording.as_ref().filter(|_| found < levels[depth - 1]) {
+                let xml_value = element.text().unwrap_or("");
+                found += record(&xml_name, xml_value, recording, config, &foreign, state, depth);
                 for attribute in element.attributes() {
-                    let xml_attribute = format!("{}{}{}", xml_name, "/@", attribute.name());
-                    xml_value = attribute.value().to_owned();
-                    let qualified_name = format!("{}{}{}", xml_attribute, LEVEL, depth);
-                    let value_filter = filters.get(&qualified_name);
-                    if value_filter.is_some() && xml_value.ne(value_filter.unwrap()) {
-                        skip = true;
-                        break;
-                    }
-                    //println!("Filtering: {} = {:?} (siblings = {})", &qualified_name, filters.get(&qualified_name), siblings);
-                    //println!("Attribute found: {} (on {} with {} found)", xml_value, recording.as_ref().unwrap(), found);
-                    found = found + record(&xml_attribute, &xml_value, recording.as_ref().unwrap(), matcher, parsed, result, depth);
+                    let xml_attribute = format!("{}{}{}", xml_name, "/@", qualified_attribute_name(attribute, namespaces, spellings));
+                    found += record(&xml_attribute, attribute.value(), recording, config, &foreign, state, depth);
                 }
-                // println!("Number found: {}", found);    
             } 
         }
         if !nodes_to_search.is_empty() && !skip {
             traverse(
                 nodes_to_search,
-                matcher,
-                filters,
-                header,
-                elements,
-                levels,
+                config,
                 namespaces,
-                parsed,
-                result,
-                output,
-                recording,
+                state,
+                recording.to_owned(),
                 siblings,
                 depth + 1,
             );
         }
     }
-    if elements.keys().all(|key| parsed.contains_key(key)) {
-        if !parsed.keys().all(|key| parsed.get(key).unwrap().is_empty()) {
-            // println!("Parsed <{}>: {:?}", current_element.unwrap(), parsed);
-            // println!("Result: {:?}", result);
-            for values in parsed.values_mut() {
-                values.clear();
+    if elements
+        .keys()
+        .filter(|key| recording.as_ref().is_none_or(|recording| on_branch(key, recording, parents)))
+        .all(|key| state.parsed.contains_key(key))
+        && !state.parsed.keys().all(|key| state.parsed.get(key).unwrap().is_empty())
+    {
+        for values in state.parsed.values_mut() {
+            values.clear();
+        }
+        // ------------------------------------------------------------------------------------------
+        let transformed = |head: &str| {
+            let column_value = match state.result.get(head) {
+                Some(Match::Value(column_value)) => column_value,
+                _ => "",
+            };
+            match transforms.get(head) {
+                Some(column_transforms) => apply_all(column_transforms, column_value),
+                None => column_value.to_owned(),
             }
-            // ------------------------------------------------------------------------------------------
-            let mut peekable_header = header.iter().peekable();
-            while let Some(head) = peekable_header.next() {
-                if let Some(Match::Value(column_value)) = result.get(head) {
-                    write!(output, "{}", column_value).expect("Cannot write to output file");
+        };
+        let row: Vec<String> = header
+            .iter()
+            .map(|head| match splits.get(head) {
+                Some((source, split, index, columns)) => split.part(&transformed(source), *index, *columns),
+                None => transformed(head),
+            })
+            .collect();
+        // exploded and pivoted values get one row each, where the other values are repeated
+        let exploded: Vec<(usize, Vec<String>, Option<usize>)> = header
+            .iter()
+            .enumerate()
+            .filter_map(|(index, head)| {
+                if let Some(Match::Values(values)) = state.result.get(head) {
+                    return Some((index, values.to_owned(), None));
                 }
-                if peekable_header.peek().is_none() {
-                    write!(output, "{}", TERMINATOR).expect("Cannot write to output file");
+                let (explosion, index_column) = explosions.get(head)?;
+                let position = index_column
+                    .as_ref()
+                    .and_then(|index_column| header.iter().position(|head| head == index_column));
+                Some((index, explosion.split(&row[index]), position))
+            })
+            .collect();
+        let count = exploded.iter().map(|(_, parts, _)| parts.len()).max().unwrap_or(1).max(1);
+        let row_position = header.iter().position(|head| head == "_row");
+        for item in 0..count {
+            let mut row = row.clone();
+            for (index, parts, position) in &exploded {
+                row[*index] = parts.get(item).cloned().unwrap_or_default();
+                if let Some(position) = position {
+                    row[*position] = if parts.len() > item { (item + 1).to_string() } else { String::default() };
+                }
+            }
+            if let Some(row_position) = row_position {
+                row[row_position] = (state.output.rows + 1).to_string();
+            }
+            let value = |column: &str| {
+                header
+                    .iter()
+                    .position(|head| head == column)
+                    .map_or("", |index| row[index].as_str())
+            };
+            if wheres.iter().all(|filter| filter.passes_row(&value)) {
+                state.output.rows += 1;
+                write!(state.output.file, "{}{}", row.join(&DELIMITER.to_string()), TERMINATOR)
+                    .expect("Cannot write to output file");
+            }
+        }
+        // ------------------------------------------------------------------------------------------
+    }
+}
+
+/// The name of the element, prefixed and spelled as in the config
+fn qualified_name(element: &Node, namespaces: &HashMap<String, String>, spellings: &Spellings) -> String {
+    let xml_name = element.tag_name().name();
+    let name = match element.tag_name().namespace().and_then(|schema_name| namespaces.get(schema_name)) {
+        Some(namespace) if !namespace.is_empty() => format!("{}:{}", namespace, xml_name),
+        _ => xml_name.to_owned(),
+    };
+    spellings.spelling(&name).cloned().unwrap_or(name)
+}
+
+/// The name of the attribute, prefixed and spelled as in the config
+fn qualified_attribute_name(attribute: &Attribute, namespaces: &HashMap<String, String>, spellings: &Spellings) -> String {
+    let name = match attribute.namespace().and_then(|schema_name| namespaces.get(schema_name)) {
+        Some(namespace) if !namespace.is_empty() => format!("{}:{}", namespace, attribute.name()),
+        _ => attribute.name().to_owned(),
+    };
+    spellings.spelling(&name).cloned().unwrap_or(name)
+}
+
+/// The qualified record element followed by the record elements it is nested in, from the innermost and out
+fn ancestry<'a>(recording: &'a str, parents: &'a HashMap<String, String>) -> Vec<&'a str> {
+    let mut ancestry = vec![recording];
+    while let Some(parent) = parents.get(*ancestry.last().unwrap()) {
+        ancestry.push(parent);
+    }
+    ancestry
+}
+
+/// Whether a record element is the one being recorded, nested in it, or one of those it is nested in,
+/// as opposed to a record element on another branch, such as an interleaved sibling of another name
+fn on_branch(element: &str, recording: &str, parents: &HashMap<String, String>) -> bool {
+    ancestry(element, parents).contains(&recording) || ancestry(recording, parents).contains(&element)
+}
+
+/// The columns of record elements on other branches than the one being recorded, which must not get values
+/// from elements that happen to have the same name on the same level. A column that is also in a record
+/// element on the branch is not foreign.
+fn foreign_columns<'a>(
+    recording: &str,
+    elements: &'a HashMap<String, Vec<String>>,
+    parents: &HashMap<String, String>,
+) -> HashSet<&'a str> {
+    let (on, off): (Vec<_>, Vec<_>) = elements
+        .iter()
+        .partition(|(element, _)| on_branch(element, recording, parents));
+    let own: HashSet<&str> = on.iter().flat_map(|(_, columns)| columns.iter().map(|column| column.as_str())).collect();
+    off.iter()
+        .flat_map(|(_, columns)| columns.iter().map(|column| column.as_str()))
+        .filter(|column| !own.contains(column))
+        .collect()
+}
+
+/// The qualified record element (name|level) that an element with the given name on a level is,
+/// where an element that matches a pattern is only used if no record element has its exact name.
+/// Record elements given as paths are not found here, since they are found by their paths.
+fn find_record_element(
+    xml_name: &str,
+    elements: &HashMap<String, Vec<String>>,
+    record_paths: &HashMap<String, String>,
+    depth: usize,
+) -> Option<String> {
+    let level = format!("{}{}", LEVEL, depth);
+    let qualified_element_name = format!("{}{}", xml_name, level);
+    if elements.contains_key(&qualified_element_name) {
+        return Some(qualified_element_name).filter(|element| !record_paths.contains_key(element));
+    }
+    elements
+        .keys()
+        .filter(|element| !record_paths.contains_key(*element))
+        .filter_map(|element| element.strip_suffix(&level).map(|pattern| (element, pattern)))
+        .filter(|(_, pattern)| is_pattern(pattern) && matches_name(pattern, xml_name))
+        .map(|(element, _)| element.to_owned())
+        .min()
+}
+
+/// The first column a where clause refers to that is not in the output
+fn unknown_column<'a>(filter: &'a Filter, header: &[String]) -> Option<&'a str> {
+    filter.names().into_iter().find(|column| !header.iter().any(|head| head == column))
+}
+
+/// Finds the record elements on a level, whether they are matched by name or given as paths. Record elements
+/// of different names may be interleaved, so they are returned in document order to produce their rows in it.
+fn find_records<'a>(
+    nodes: &[Rc<Node<'a, 'a>>],
+    config: &Config,
+    namespaces: &HashMap<String, String>,
+    depth: usize,
+) -> Vec<(String, Node<'a, 'a>)> {
+    let level = format!("{}{}", LEVEL, depth);
+    let mut records: Vec<(String, Node)> = nodes
+        .iter()
+        .filter(|el| el.is_element())
+        .filter_map(|el| {
+            let xml_name = qualified_name(el, namespaces, &config.spellings);
+            find_record_element(&xml_name, &config.elements, &config.record_paths, depth).map(|record_element| (record_element, **el))
+        })
+        .collect();
+    for (record_element, path) in config.record_paths.iter().filter(|(record_element, _)| record_element.ends_with(&level)) {
+        records.extend(
+            select_elements(nodes, path, namespaces, &config.spellings)
+                .into_iter()
+                .map(|record| (record_element.to_owned(), record)),
+        );
+    }
+    records.sort_by(|(element, record), (other_element, other)| {
+        record.id().get().cmp(&other.id().get()).then_with(|| element.cmp(other_element))
+    });
+    records
+}
+
+/// Finds the values of a path in a filter or directive, such as `name/@attribute`, `../name` or
+/// `name//descendant`. The path is relative to the parent of the level, but only reaches the given
+/// nodes on the level.
+fn select<'a>(
+    nodes: &[Rc<Node<'a, 'a>>],
+    path: &str,
+    namespaces: &HashMap<String, String>,
+    spellings: &Spellings,
+) -> Vec<&'a str> {
+    let attribute = match path.rsplit_once('/') {
+        Some((elements, attribute)) if attribute.starts_with('@') => Some((elements, &attribute[1..])),
+        None if path.starts_with('@') => Some(("", &path[1..])),
+        _ => None,
+    };
+    match attribute {
+        Some((path, attribute_name)) => select_elements(nodes, path, namespaces, spellings)
+            .iter()
+            .filter_map(|el| {
+                el.attributes()
+                    .iter()
+                    .find(|attribute| qualified_attribute_name(attribute, namespaces, spellings) == attribute_name)
+                    .map(|attribute| attribute.value())
+            })
+            .collect(),
+        None => select_elements(nodes, path, namespaces, spellings)
+            .iter()
+            .map(|el| el.text().unwrap_or(""))
+            .collect(),
+    }
+}
+
+/// Finds the values of a path in a directive, which may also end with `name()`, `local-name()` or `namespace-uri()`
+/// of the elements, `outer-xml()`, `inner-xml()` or `text()` for their contents, `wkt()`, `ewkt()`,
+/// `sqlserver-wkt()`, `wkb()`, `ewkb()` or `srid()` for a GML geometry within them, or `@*` for all of their
+/// attributes, as `name="value"` pairs or a JSON object
+fn select_generic(
+    nodes: &[Rc<Node>],
+    path: &str,
+    namespaces: &HashMap<String, String>,
+    spellings: &Spellings,
+    occurrence: &Occurrence,
+) -> Vec<String> {
+    let (elements_path, last) = match path.rsplit_once('/') {
+        Some((elements_path, last)) => (elements_path, last),
+        None => ("", path),
+    };
+    let elements = || select_elements(nodes, elements_path, namespaces, spellings);
+    match last {
+        "name()" => elements().iter().map(|el| qualified_name(el, namespaces, spellings)).collect(),
+        "local-name()" => elements().iter().map(|el| el.tag_name().name().to_owned()).collect(),
+        "namespace-uri()" => elements()
+            .iter()
+            .map(|el| el.tag_name().namespace().unwrap_or_default().to_owned())
+            .collect(),
+        // tabs and line breaks would break the row, so they are replaced by spaces
+        "outer-xml()" => elements().iter().map(|el| single_line(outer_xml(el))).collect(),
+        "inner-xml()" => elements().iter().map(|el| single_line(inner_xml(el))).collect(),
+        "text()" => elements()
+            .iter()
+            .map(|el| {
+                let text: String = el.descendants().filter(|node| node.is_text()).filter_map(|node| node.text()).collect();
+                single_line(&text)
+            })
+            .collect(),
+        "wkt()" | "ewkt()" | "sqlserver-wkt()" | "wkb()" | "ewkb()" | "srid()" => elements()
+            .iter()
+            .filter_map(Geometry::read)
+            .filter_map(|geometry| match last {
+                "wkt()" => Some(geometry.wkt(false)),
+                "ewkt()" => Some(geometry.wkt(true)),
+                "sqlserver-wkt()" => Some(geometry.sql_server_wkt()),
+                "wkb()" => Some(geometry.wkb(false)),
+                "ewkb()" => Some(geometry.wkb(true)),
+                _ => geometry.srid.map(|srid| srid.to_string()),
+            })
+            .collect(),
+        "@*" => elements()
+            .iter()
+            .filter(|el| !el.attributes().is_empty())
+            .map(|el| {
+                if *occurrence == Occurrence::Json {
+                    let members: Vec<String> = el
+                        .attributes()
+                        .iter()
+                        .map(|attribute| {
+                            let name = qualified_attribute_name(attribute, namespaces, spellings);
+                            format!("{}:{}", json_string(&name), json_string(attribute.value()))
+                        })
+                        .collect();
+                    format!("{{{}}}", members.join(","))
                 } else {
-                    write!(output, "{}", DELIMITER).expect("Cannot write to output file");
+                    let pairs: Vec<String> = el
+                        .attributes()
+                        .iter()
+                        .map(|attribute| {
+                            let name = qualified_attribute_name(attribute, namespaces, spellings);
+                            format!("{}=\"{}\"", name, attribute.value().replace('"', "&quot;"))
+                        })
+                        .collect();
+                    pairs.join(" ")
                 }
+            })
+            .collect(),
+        _ => select(nodes, path, namespaces, spellings).into_iter().map(|value| value.to_owned()).collect(),
+    }
+}
+
+fn single_line(value: &str) -> String {
+    value.replace("\r\n", " ").replace(['\r', '\n', DELIMITER], " ")
+}
+
+/// The element as it is written in the file, including its start and end tags
+fn outer_xml<'a>(element: &Node<'a, 'a>) -> &'a str {
+    &element.document().input_text()[element.range()]
+}
+
+/// The contents of the element as they are written in the file, between its start and end tags
+fn inner_xml<'a>(element: &Node<'a, 'a>) -> &'a str {
+    let outer = outer_xml(element);
+    // the start tag ends at the first > that is not within a quoted attribute value
+    let mut quote: Option<char> = None;
+    let mut start_tag_end = outer.len();
+    for (index, character) in outer.char_indices() {
+        match (quote, character) {
+            (None, '"' | '\'') => quote = Some(character),
+            (Some(open), _) if open == character => quote = None,
+            (None, '>') => {
+                start_tag_end = index + 1;
+                break;
+            }
+            _ => (),
+        }
+    }
+    if outer[..start_tag_end].ends_with("/>") {
+        return "";
+    }
+    let end_tag_start = outer.rfind("</").unwrap_or(outer.len()).max(start_tag_end);
+    &outer[start_tag_end..end_tag_start]
+}
+
+/// Finds the elements of a path without attributes, where an empty path is the parent of the level
+fn select_elements<'a>(
+    nodes: &[Rc<Node<'a, 'a>>],
+    path: &str,
+    namespaces: &HashMap<String, String>,
+    spellings: &Spellings,
+) -> Vec<Node<'a, 'a>> {
+    let mut current: Vec<Node> = nodes.iter().filter(|el| el.is_element()).map(|el| **el).collect();
+    let mut on_level = true;
+    let mut descendants = false;
+    let parents = |current: &[Node<'a, 'a>]| {
+        let mut parents: Vec<Node> = Vec::default();
+        for parent in current.iter().filter_map(|el| el.parent_element()) {
+            if !parents.iter().any(|other| other.id() == parent.id()) {
+                parents.push(parent);
             }
-            // ------------------------------------------------------------------------------------------
+        }
+        parents
+    };
+    for step in path.split('/') {
+        if step.is_empty() {
+            descendants = true;
+            continue;
+        }
+        // a position such as name[2] or name[last()] picks one of the elements with the same parent
+        let (step, position) = match step.split_once('[') {
+            Some((name, position)) => (name, position.strip_suffix(']')),
+            None => (step, None),
+        };
+        let candidates: Vec<Node> = match (step, on_level, descendants) {
+            (".", true, _) => parents(&current),
+            (".", false, _) => current,
+            ("..", true, _) => parents(&parents(&current)),
+            ("..", false, _) => parents(&current),
+            (_, true, false) => current,
+            (_, true, true) => current.iter().flat_map(|el| el.descendants()).collect(),
+            (_, false, false) => current.iter().flat_map(|el| el.children()).collect(),
+            (_, false, true) => current.iter().flat_map(|el| el.descendants().skip(1)).collect(),
+        };
+        current = match step {
+            "." | ".." => candidates,
+            _ => candidates
+                .into_iter()
+                .filter(|el| el.is_element() && matches_name(step, &qualified_name(el, namespaces, spellings)))
+                .collect(),
+        };
+        if let Some(position) = position {
+            current = at_position(current, position);
+        }
+        on_level = false;
+        descendants = false;
+    }
+    if on_level {
+        parents(&current)
+    } else {
+        current
+    }
+}
+
+/// Keeps the element at a position, counted from 1 or given as `last()`, among the elements with the same parent
+fn at_position<'a>(elements: Vec<Node<'a, 'a>>, position: &str) -> Vec<Node<'a, 'a>> {
+    // elements with the same parent need not be next to each other, as after a `//` step
+    let mut groups: BTreeMap<Option<u32>, Vec<Node>> = BTreeMap::default();
+    for element in elements {
+        groups.entry(element.parent().map(|parent| parent.id().get())).or_default().push(element);
+    }
+    let mut found: Vec<Node> = groups
+        .into_values()
+        .filter_map(|group| match position.trim() {
+            "last()" => group.last().copied(),
+            position => position
+                .parse::<usize>()
+                .ok()
+                .and_then(|position| position.checked_sub(1))
+                .and_then(|index| group.get(index).copied()),
+        })
+        .collect();
+    // node ids follow the order of the document
+    found.sort_by_key(|element| element.id().get());
+    found
+}
+
+/// Whether a path in a filter or directive starts from a record element on the level
+fn starts_on_record(path: &str, elements: &HashMap<String, Vec<String>>, depth: usize) -> bool {
+    let element_name = path.split('/').next().unwrap_or_default();
+    elements.contains_key(&format!("{}{}{}", element_name, LEVEL, depth))
+}
+
+/// Checks if any of the filters on a level fails for the elements, or their attributes, on it.
+/// Filters that refer to a record element are checked for each record, and other filters for the
+/// whole level.
+fn rejected(
+    nodes: &[Rc<Node>],
+    config: &Config,
+    namespaces: &HashMap<String, String>,
+    records: bool,
+    depth: usize,
+) -> bool {
+    let values = |path: &str| select(nodes, path, namespaces, &config.spellings);
+    let on_record = |path: &str| starts_on_record(path, &config.elements, depth);
+    config.filters.get(&depth).is_some_and(|filters| {
+        filters
+            .iter()
+            .filter(|filter| filter.names().into_iter().any(on_record) == records)
+            .any(|filter| !filter.passes(&values))
+    })
+}
+
+/// Records the values of each path selected on a level, combined as given by its occurrence, and the
+/// names and values of each pivot. Paths that start from a record element are
+/// selected for each record, and other paths for the whole level.
+fn record_selections(
+    nodes: &[Rc<Node>],
+    config: &Config,
+    namespaces: &HashMap<String, String>,
+    recording: &String,
+    state: &mut State,
+    records: bool,
+    depth: usize,
+) {
+    let Config { selections, pivots, elements, parents, spellings, .. } = config;
+    let foreign = foreign_columns(recording, elements, parents);
+    let on_record = |path: &str| starts_on_record(path, elements, depth);
+    // directives in the block of another record element may be on the same level
+    let elsewhere = |element: &String| !element.is_empty() && element != recording;
+    for Selection { path, column, occurrence, element } in selections.get(&depth).into_iter().flatten() {
+        if on_record(path) != records || foreign.contains(column.as_str()) || elsewhere(element) {
+            continue;
+        }
+        let values = select_generic(nodes, path, namespaces, spellings, occurrence);
+        let values: Vec<&str> = values.iter().map(|value| value.as_str()).collect();
+        let xml_value = match (occurrence, path.ends_with("@*")) {
+            // the attributes of a single element are output as an object rather than as an array of objects
+            (Occurrence::Json, true) if values.len() == 1 => Some(values[0].to_owned()),
+            (Occurrence::Json, true) => Some(format!("[{}]", values.join(","))),
+            _ => occurrence.apply(&values),
+        };
+        if let Some(xml_value) = xml_value {
+            state.result.insert(column.to_owned(), Match::Value(xml_value));
+            state.parsed.entry(recording.to_owned()).or_default().insert(column.to_owned());
+        }
+    }
+    for Pivot { path, name_column, value_column, element } in pivots.get(&depth).into_iter().flatten() {
+        if on_record(path) != records || foreign.contains(name_column.as_str()) || elsewhere(element) {
+            continue;
+        }
+        let (names, values): (Vec<String>, Vec<String>) = match path.strip_suffix("/@*") {
+            Some(path) => select_elements(nodes, path, namespaces, spellings)
+                .iter()
+                .flat_map(|el| el.attributes())
+                .map(|attribute| (qualified_attribute_name(attribute, namespaces, spellings), attribute.value().to_owned()))
+                .unzip(),
+            None => select_elements(nodes, path, namespaces, spellings)
+                .iter()
+                .flat_map(|el| el.children().filter(|child| child.is_element()))
+                .map(|child| (qualified_name(&child, namespaces, spellings), child.text().unwrap_or("").trim().to_owned()))
+                .unzip(),
+        };
+        state.result.insert(name_column.to_owned(), Match::Values(names));
+        state.result.insert(value_column.to_owned(), Match::Values(values));
+        state.parsed.entry(recording.to_owned()).or_default().insert(name_column.to_owned());
+    }
+}
+
+/// Records where the record element is found, for those metadata columns that are in the output
+fn locate(element: &Node, result: &mut HashMap<String, Match>) {
+    if result.contains_key("_ordinal") {
+        let ordinal = element
+            .prev_siblings()
+            .filter(|el| el.has_tag_name(element.tag_name()))
+            .count();
+        result.insert("_ordinal".to_owned(), Match::Value(ordinal.to_string()));
+    }
+    if result.contains_key("_offset") {
+        result.insert("_offset".to_owned(), Match::Value(element.range().start.to_string()));
+    }
+    if result.contains_key("_line") || result.contains_key("_column") {
+        let position = element.document().text_pos_at(element.range().start);
+        if result.contains_key("_line") {
+            result.insert("_line".to_owned(), Match::Value(position.row.to_string()));
+        }
+        if result.contains_key("_column") {
+            result.insert("_column".to_owned(), Match::Value(position.col.to_string()));
         }
     }
 }
@@ -242,20 +780,20 @@ fn record(
     xml_name: &str,
     xml_value: &str,
     recording: &String,
-    matcher: &HashMap<String, String>,
-    parsed: &mut HashMap<String, HashSet<String>>,
-    result: &mut HashMap<String, Match>,
+    config: &Config,
+    foreign: &HashSet<&str>,
+    state: &mut State,
     depth: usize,
 ) -> usize {
     let element = format!("{}{}{}", xml_name, LEVEL, depth);
     let mut found: usize = 0;
-    // println!("Looking for: {} in <{}>", &element, recording);
-    if let Some(column) = matcher.get(&element) {
-        // println!("Found on level {}: {} = {}", depth, &element, xml_value);
-        result.insert(column.to_owned(), Match::Value(xml_value.to_owned()));
-        let values = parsed.entry(recording.to_owned()).or_insert(HashSet::new());        
-        values.insert(column.to_owned());
-        found = 1;
+    if let Some(columns) = config.matcher.get(&element) {
+        let values = state.parsed.entry(recording.to_owned()).or_default();
+        for column in columns.iter().filter(|column| !foreign.contains(column.as_str())) {
+            state.result.insert(column.to_owned(), Match::Value(xml_value.to_owned()));
+            values.insert(column.to_owned());
+        }
+        found = columns.len();
     }
     found
 }
@@ -266,23 +804,83 @@ fn main() {
         outfile,
         parser,
         metadata,
+        batch,
         quiet,
+        captures,
+        params,
+        wheres: where_args,
     } = Args::parse();
 
     // read the config containing the mapping between elements and columns
     let configuration = read_to_string(parser);
     match configuration {
-        Ok(config) => {
-            let (
-                matcher, 
-                filters,
-                mut header, 
-                elements, 
-                levels
-            ) = parse(&config);
-            let mut result: HashMap<String, Match> = HashMap::default();
-            if metadata {
-                header.push("_path".to_owned());
+        Ok(text) => {
+            let mut config = parse(&text);
+            if let Some(captures) = captures {
+                match file_pattern(&captures) {
+                    Ok((regex, columns)) => {
+                        config.header.extend(columns);
+                        config.file_patterns.push(regex);
+                    }
+                    Err(error) => {
+                        println!("{}", error);
+                        std::process::exit(1);
+                    }
+                }
+            }
+
+            // resolve the values of columns that do not come from the xml
+            let mut params_by_key: HashMap<String, String> = HashMap::default();
+            for param in params {
+                match param.split_once('=') {
+                    Some((key, value)) => {
+                        params_by_key.insert(key.to_owned(), value.to_owned());
+                    }
+                    None => {
+                        println!("The parameter {} must be given as key=value.", param);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            let mut constants: HashMap<String, String> = HashMap::default();
+            for (column, source) in &config.sources {
+                let value = match source {
+                    Source::Const(value) => value.to_owned(),
+                    Source::Param(key) => match params_by_key.get(key) {
+                        Some(value) => value.to_owned(),
+                        None => {
+                            println!("The config uses the parameter {}, specify it using --param {}=<value>", key, key);
+                            std::process::exit(1);
+                        }
+                    },
+                    Source::Env(variable) => std::env::var(variable).unwrap_or_default(),
+                };
+                constants.insert(column.to_owned(), value);
+            }
+            for column in &metadata {
+                config.header.push(column.column().to_owned());
+            }
+            let started = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs() as i64)
+                .unwrap_or_default();
+            let batch = batch.unwrap_or_else(|| started.to_string());
+
+            // conditions on the output rows, from the config and the command line
+            for where_arg in &where_args {
+                match parse_where(where_arg) {
+                    Ok(filter) => config.wheres.push(filter),
+                    Err(error) => {
+                        println!("Could not parse the where clause:\n{}", error);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            for filter in &config.wheres {
+                if let Some(column) = unknown_column(filter, &config.header) {
+                    println!("The where clause refers to the column {}, which is not in the output.", column);
+                    std::process::exit(1);
+                }
             }
 
             // parse the arguments to get the filename glob pattern
@@ -290,14 +888,21 @@ fn main() {
                 println!("Finding files matching: {}", &filename);
                 println!("Results are stored in: {}", &outfile);
             }
-            let mut output = File::create(outfile).unwrap();
-            let mut peekable_header = header.iter().peekable();
+            let mut state = State {
+                parsed: HashMap::default(),
+                result: HashMap::default(),
+                output: Output {
+                    file: File::create(outfile).unwrap(),
+                    rows: 0,
+                },
+            };
+            let mut peekable_header = config.header.iter().peekable();
             while let Some(head) = peekable_header.next() {
-                write!(output, "{}", head).expect("Cannot write to output file");
+                write!(state.output.file, "{}", head).expect("Cannot write to output file");
                 if peekable_header.peek().is_none() {
-                    write!(output, "{}", TERMINATOR).expect("Cannot write to output file");
+                    write!(state.output.file, "{}", TERMINATOR).expect("Cannot write to output file");
                 } else {
-                    write!(output, "{}", DELIMITER).expect("Cannot write to output file");
+                    write!(state.output.file, "{}", DELIMITER).expect("Cannot write to output file");
                 }
             }
 
@@ -310,7 +915,7 @@ fn main() {
                         let mut popping = path.pop();
                         let mut local_path: Option<String> = None;
                         while popping {
-                            if !common_path.is_some() {
+                            if common_path.is_none() {
                                 common_path = Some(path.display().to_string());
                             }
                             if common_path.as_ref().unwrap().starts_with(&path.display().to_string()) {
@@ -324,7 +929,6 @@ fn main() {
                     Err(_e) => None
                 }
             }
-            // println!("Matching part of filename: {:?}", common_path);
 
             // use the glob to find matching files
             let matching_files = glob(&filename).expect("Failed to read glob pattern");
@@ -332,8 +936,8 @@ fn main() {
                 match entry {
                     Ok(path) => {
                         let mut filename = path.display().to_string();
-                        if common_path.is_some() && !common_path.as_ref().unwrap().is_empty() {
-                            filename = filename.replace(common_path.as_ref().unwrap(), "");
+                        if let Some(common_path) = common_path.as_ref().filter(|path| !path.is_empty()) {
+                            filename = filename.replace(common_path, "");
                             if filename.starts_with('/') || filename.starts_with('\\') {
                                 filename = filename[1..].to_string();
                             }
@@ -343,41 +947,79 @@ fn main() {
                         }
                         let contents = fs::read_to_string(&path)
                             .expect("Something went wrong reading the file");
+                        state.output.rows = 0;
                         let doc =
                             roxmltree::Document::parse(&contents).expect("Could not parse the xml");
-                        result.extend(header.iter().map(|head| (head.to_owned(), Match::Nothing)));
-                        if metadata {
-                            result.insert("_path".to_owned(), Match::Value(filename));
+                        state.result.extend(config.header.iter().map(|head| (head.to_owned(), Match::Nothing)));
+                        for column in &metadata {
+                            let value = match column {
+                                Metadata::Path => filename.to_owned(),
+                                Metadata::AbsolutePath => fs::canonicalize(&path)
+                                    .map(|absolute| absolute.display().to_string())
+                                    .unwrap_or_default(),
+                                Metadata::Size => contents.len().to_string(),
+                                Metadata::Modified => fs::metadata(&path)
+                                    .and_then(|file| file.modified())
+                                    .ok()
+                                    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                                    .map(|duration| utc_timestamp(duration.as_secs() as i64))
+                                    .unwrap_or_default(),
+                                Metadata::Hash => Sha256::digest(contents.as_bytes())
+                                    .iter()
+                                    .map(|byte| format!("{:02x}", byte))
+                                    .collect(),
+                                Metadata::Started => utc_timestamp(started),
+                                Metadata::Batch => batch.to_owned(),
+                                Metadata::Row
+                                | Metadata::Ordinal
+                                | Metadata::Line
+                                | Metadata::Column
+                                | Metadata::Offset => continue,
+                            };
+                            state.result.insert(column.column().to_owned(), Match::Value(value));
+                        }
+                        for (column, value) in &constants {
+                            state.result.insert(column.to_owned(), Match::Value(value.to_owned()));
+                        }
+                        if let Some(name) = path.file_name() {
+                            let name = name.to_string_lossy();
+                            for regex in &config.file_patterns {
+                                match regex.captures(&name) {
+                                    Some(groups) => {
+                                        for column in regex.capture_names().flatten() {
+                                            if let Some(group) = groups.name(column) {
+                                                state.result.insert(column.to_owned(), Match::Value(group.as_str().to_owned()));
+                                            }
+                                        }
+                                    }
+                                    None => {
+                                        if !quiet {
+                                            println!("The file name does not match the pattern: {}", regex);
+                                        }
+                                    }
+                                }
+                            }
                         }
-                        let mut parsed: HashMap<String, HashSet<String>> = HashMap::default();
+                        state.parsed = HashMap::default();
                         let root = doc.root_element();
+                        // namespaces declared in the config are matched by their URI, whatever their prefix in the file,
+                        // while other namespaces keep the prefix they have on the root element
                         let mut namespaces: HashMap<String, String> = HashMap::default();
                         for namespace in root.namespaces() {
-                            match namespace.name() {
-                                Some(name) => {
+                            if let Some(name) = namespace.name() {
+                                if !config.namespaces.values().any(|prefix| prefix == name) {
                                     namespaces.insert(namespace.uri().to_owned(), name.to_owned());
-                                },
-                                None => ()
-                            };
+                                }
+                            }
+                        }
+                        namespaces.extend(config.namespaces.clone());
+                        // without prefixes for any namespace, all names are local
+                        if config.local_names {
+                            namespaces.clear();
                         }
-                        //println!("namespaces: {:?}", &namespaces);
                         let nodes = vec![Rc::new(root)];
 
-                        traverse(
-                            nodes,
-                            &matcher,
-                            &filters,
-                            &header,
-                            &elements,
-                            &levels,
-                            &namespaces,
-                            &mut parsed,
-                            &mut result,
-                            &mut output,
-                            None,
-                            false,
-                            1,
-                        );
+                        traverse(nodes, &config, &namespaces, &mut state, None, false, 1);
                     }
                     Err(e) => println!("{:?}", e),
                 }
@@ -391,3 +1033,182 @@ fn main() {
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(elements: &[Node]) -> Vec<String> {
+        elements.iter().map(|el| el.text().unwrap_or_default().to_owned()).collect()
+    }
+
+    /// Parses a document with a config, and reads back the rows written to a file named after the test
+    fn rows(configuration: &str, xml: &str, test: &str) -> Vec<String> {
+        let config = parse(configuration);
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        let path = std::env::temp_dir().join(format!("unstruct-{}-{}.txt", test, std::process::id()));
+        let mut state = State {
+            parsed: HashMap::default(),
+            result: config.header.iter().map(|head| (head.to_owned(), Match::Nothing)).collect(),
+            output: Output { file: File::create(&path).unwrap(), rows: 0 },
+        };
+        traverse(vec![Rc::new(doc.root_element())], &config, &HashMap::default(), &mut state, None, false, 1);
+        let rows = read_to_string(&path).unwrap().lines().map(|row| row.to_owned()).collect();
+        fs::remove_file(&path).unwrap();
+        rows
+    }
+
+    #[test]
+    fn every_record_element_given_as_a_path_is_visited() {
+        let configuration = r#"
+            <root>
+            rn = "root/@name"
+            {
+                <as/a> { an = "n" }
+                <bs/b> { bn = "n" }
+            }
+        "#;
+        let xml = r#"<root name="r"><as><a><n>a1</n></a></as><bs><b><n>b1</n></b></bs></root>"#;
+        assert_eq!(rows(configuration, xml, "paths"), ["r\ta1\t", "r\t\tb1"]);
+    }
+
+    #[test]
+    fn records_given_as_descendant_paths_are_visited_in_document_order() {
+        let configuration = r#"
+            <root>
+            rn = "root/@name"
+            {
+                <//b> { bn = "n" }
+                <//a> { an = "n" }
+            }
+        "#;
+        let xml = r#"<root name="r"><w><a><n>a1</n></a><b><n>b1</n></b><a><n>a2</n></a></w></root>"#;
+        assert_eq!(rows(configuration, xml, "descendants"), ["r\t\ta1", "r\tb1\t", "r\t\ta2"]);
+    }
+
+    #[test]
+    fn records_of_different_names_are_interleaved() {
+        let configuration = r#"
+            <root>
+            rn = "root/@name"
+            {
+                <a> { an = "n" }
+                <b> { bn = "n" }
+            }
+        "#;
+        let xml = r#"<root name="r"><a><n>a1</n></a><b><n>b1</n></b><a><n>a2</n></a></root>"#;
+        assert_eq!(rows(configuration, xml, "names"), ["r\ta1\t", "r\t\tb1", "r\ta2\t"]);
+    }
+
+    #[test]
+    fn records_matched_by_name_and_by_path_are_interleaved() {
+        let configuration = r#"
+            <root>
+            rn = "root/@name"
+            {
+                <c> { cn = "n" }
+                <//a> { an = "n" }
+            }
+        "#;
+        let xml = r#"<root name="r"><c><n>c1</n></c><x><a><n>a1</n></a></x><c><n>c2</n></c></root>"#;
+        assert_eq!(rows(configuration, xml, "interleaved"), ["r\tc1\t", "r\t\ta1", "r\tc2\t"]);
+    }
+
+    #[test]
+    fn comparisons_on_ancestors_pass_without_a_value_unless_any_is_used() {
+        let configuration = |filter: &str| {
+            format!(
+                r#"
+                {{
+                    <record> {{
+                        id = "id"
+                        {{
+                            <volume> {{
+                                {}
+                                uplink = "uplink"
+                            }}
+                        }}
+                    }}
+                }}
+                "#,
+                filter
+            )
+        };
+        let xml = r#"<cdr>
+            <record><id>1</id><duration unit="seconds">5</duration><volumes><volume><uplink>10</uplink></volume></volumes></record>
+            <record><id>2</id><volumes><volume><uplink>20</uplink></volume></volumes></record>
+            <record><id>3</id><duration unit="minutes">1</duration><volumes><volume><uplink>30</uplink></volume></volumes></record>
+        </cdr>"#;
+        // a record whose volumes are all filtered out still gets a row of its own, without an uplink
+        let volumes = |configuration: &str, test: &str| -> Vec<String> {
+            rows(configuration, xml, test)
+                .iter()
+                .filter_map(|row| row.split_once('\t'))
+                .filter(|(_, uplink)| !uplink.is_empty())
+                .map(|(id, _)| id.to_owned())
+                .collect()
+        };
+        let all = configuration(r#"["../../duration/@unit" = "seconds"]"#);
+        assert_eq!(volumes(&all, "ancestors-all"), ["1", "2"]);
+        let any = configuration(r#"[any "../../duration/@unit" = "seconds"]"#);
+        assert_eq!(volumes(&any, "ancestors-any"), ["1"]);
+    }
+
+    #[test]
+    fn where_clauses_may_only_refer_to_columns_in_the_output() {
+        let header: Vec<String> = ["changeTime", "recordOpeningTime"].map(String::from).to_vec();
+        let known = parse_where("changeTime >= recordOpeningTime and not changeTime missing").unwrap();
+        assert_eq!(unknown_column(&known, &header), None);
+        let unknown = parse_where("changeTime >= recordOpeningtime").unwrap();
+        assert_eq!(unknown_column(&unknown, &header), Some("recordOpeningtime"));
+    }
+
+    /// The inner xml of the first element with the name in a document
+    fn inner(xml: &str, name: &str) -> String {
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        let element = doc.descendants().find(|el| el.has_tag_name(name)).unwrap();
+        inner_xml(&element).to_owned()
+    }
+
+    #[test]
+    fn inner_xml_starts_after_the_start_tag() {
+        assert_eq!(inner(r#"<r><a x="1>2" y='>'>text<b/></a><a>next</a></r>"#, "a"), "text<b/>");
+        assert_eq!(inner(r#"<r><a x="it's">'quoted'</a></r>"#, "a"), "'quoted'");
+    }
+
+    #[test]
+    fn inner_xml_of_a_self_closing_element_is_empty() {
+        assert_eq!(inner(r#"<r><a x="/>"/><a>next</a></r>"#, "a"), "");
+        assert_eq!(inner("<r><a/></r>", "a"), "");
+        assert_eq!(inner("<r><a></a></r>", "a"), "");
+    }
+
+    #[test]
+    fn inner_xml_keeps_cdata() {
+        assert_eq!(inner("<r><a><![CDATA[x</y>]]></a></r>", "a"), "<![CDATA[x</y>]]>");
+        assert_eq!(inner("<r><a><![CDATA[</a>]]> <b>1</b></a></r>", "a"), "<![CDATA[</a>]]> <b>1</b>");
+    }
+
+    #[test]
+    fn at_position_groups_siblings_that_are_not_adjacent() {
+        let doc = roxmltree::Document::parse("<a><x>1</x><b><x>2</x></b><x>3</x></a>").unwrap();
+        // the level is the children of <a>, as in the block of a record element <a>
+        let nodes: Vec<Rc<Node>> = doc.root_element().children().map(Rc::new).collect();
+        let namespaces = HashMap::default();
+        let spellings = Spellings::default();
+        let first = select_elements(&nodes, "//x[1]", &namespaces, &spellings);
+        assert_eq!(texts(&first), ["1", "2"]);
+        let last = select_elements(&nodes, "//x[last()]", &namespaces, &spellings);
+        assert_eq!(texts(&last), ["2", "3"]);
+    }
+
+    #[test]
+    fn at_position_counts_from_one() {
+        let doc = roxmltree::Document::parse("<a><x>1</x><x>2</x><x>3</x></a>").unwrap();
+        let elements: Vec<Node> = doc.root_element().children().filter(|el| el.is_element()).collect();
+        assert_eq!(texts(&at_position(elements.clone(), "2")), ["2"]);
+        assert_eq!(texts(&at_position(elements.clone(), "last()")), ["3"]);
+        assert!(at_position(elements.clone(), "0").is_empty());
+        assert!(at_position(elements, "4").is_empty());
+    }
+}
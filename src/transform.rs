@@ -0,0 +1,693 @@
+use crate::filter::ordering;
+use regex::Regex;
+
+/// A built-in function that can be applied to a value before it is output
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transform {
+    Trim,
+    TrimStart,
+    TrimEnd,
+    Upper,
+    Lower,
+    Substring(usize, Option<usize>),
+    Replace(String, String),
+    PadStart(usize, char),
+    PadEnd(usize, char),
+    Default(String),
+    Timestamp(bool),
+    Tbcd,
+    Hex,
+    Base64(bool),
+}
+
+/// How the values of an element that occurs several times are combined into one value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Occurrence {
+    First,
+    Last,
+    /// All values joined with the separator
+    Join(String),
+    Count,
+    Sum,
+    Min,
+    Max,
+    /// All values as a JSON array of strings
+    Json,
+}
+
+/// How a value is split into several values, each of which is output in a row of its own
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explosion {
+    /// Splits on whitespace if `None`
+    pub delimiter: Option<String>,
+    /// The number of parts that make up each value, such as 3 for coordinates with x, y and z
+    pub group: usize,
+}
+
+/// How a value is split into the parts that are output in several columns
+#[derive(Debug, Clone)]
+pub enum Split {
+    Delimiter(String),
+    /// The groups of a regular expression, in order
+    Capture(Regex),
+}
+
+impl Transform {
+    /// Creates the transform called `name`, where `arguments` are the ones following the value
+    pub fn new(name: &str, arguments: &[String]) -> Result<Transform, String> {
+        let count = |allowed: &[usize]| {
+            if allowed.contains(&arguments.len()) {
+                Ok(())
+            } else {
+                Err(format!(
+                    "The function {}() takes {} argument(s) after the value, but {} were given",
+                    name,
+                    allowed.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(" or "),
+                    arguments.len()
+                ))
+            }
+        };
+        let number = |index: usize| {
+            arguments[index].parse::<usize>().map_err(|_| {
+                format!("The function {}() expects a number, but got: {}", name, arguments[index])
+            })
+        };
+        let character = |index: usize| match arguments.get(index) {
+            None => Ok(' '),
+            Some(argument) if argument.chars().count() == 1 => Ok(argument.chars().next().unwrap()),
+            Some(argument) => Err(format!(
+                "The function {}() expects a single character, but got: {}",
+                name, argument
+            )),
+        };
+        match name {
+            "trim" => count(&[0]).map(|_| Transform::Trim),
+            "ltrim" => count(&[0]).map(|_| Transform::TrimStart),
+            "rtrim" => count(&[0]).map(|_| Transform::TrimEnd),
+            "upper" => count(&[0]).map(|_| Transform::Upper),
+            "lower" => count(&[0]).map(|_| Transform::Lower),
+            "substring" => {
+                count(&[1, 2])?;
+                let length = if arguments.len() == 2 { Some(number(1)?) } else { None };
+                Ok(Transform::Substring(number(0)?, length))
+            }
+            "replace" => {
+                count(&[2])?;
+                Ok(Transform::Replace(arguments[0].to_owned(), arguments[1].to_owned()))
+            }
+            "lpad" => {
+                count(&[1, 2])?;
+                Ok(Transform::PadStart(number(0)?, character(1)?))
+            }
+            "rpad" => {
+                count(&[1, 2])?;
+                Ok(Transform::PadEnd(number(0)?, character(1)?))
+            }
+            "default" => {
+                count(&[1])?;
+                Ok(Transform::Default(arguments[0].to_owned()))
+            }
+            "timestamp" => count(&[0]).map(|_| Transform::Timestamp(false)),
+            "timestamp_utc" => count(&[0]).map(|_| Transform::Timestamp(true)),
+            "tbcd" => count(&[0]).map(|_| Transform::Tbcd),
+            "hex" => count(&[0]).map(|_| Transform::Hex),
+            "base64" => count(&[0]).map(|_| Transform::Base64(false)),
+            "base64_hex" => count(&[0]).map(|_| Transform::Base64(true)),
+            _ => Err(format!("Unknown function: {}()", name)),
+        }
+    }
+
+    pub fn apply(&self, value: &str) -> String {
+        match self {
+            Transform::Trim => value.trim().to_owned(),
+            Transform::TrimStart => value.trim_start().to_owned(),
+            Transform::TrimEnd => value.trim_end().to_owned(),
+            Transform::Upper => value.to_uppercase(),
+            Transform::Lower => value.to_lowercase(),
+            Transform::Substring(start, length) => {
+                let characters = value.chars().skip(*start);
+                match length {
+                    Some(length) => characters.take(*length).collect(),
+                    None => characters.collect(),
+                }
+            }
+            Transform::Replace(from, to) => value.replace(from, to),
+            Transform::PadStart(_, _) | Transform::PadEnd(_, _) if value.is_empty() => String::new(),
+            Transform::PadStart(width, fill) => {
+                let padding = width.saturating_sub(value.chars().count());
+                std::iter::repeat_n(*fill, padding).chain(value.chars()).collect()
+            }
+            Transform::PadEnd(width, fill) => {
+                let padding = width.saturating_sub(value.chars().count());
+                value.chars().chain(std::iter::repeat_n(*fill, padding)).collect()
+            }
+            Transform::Default(default) => {
+                if value.is_empty() {
+                    default.to_owned()
+                } else {
+                    value.to_owned()
+                }
+            }
+            Transform::Timestamp(utc) => timestamp(value, *utc).unwrap_or_else(|| value.to_owned()),
+            Transform::Tbcd => tbcd(value).unwrap_or_else(|| value.to_owned()),
+            Transform::Hex => from_hex(value)
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .unwrap_or_else(|| value.to_owned()),
+            Transform::Base64(false) => from_base64(value)
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .unwrap_or_else(|| value.to_owned()),
+            Transform::Base64(true) => from_base64(value)
+                .map(|bytes| bytes.iter().map(|byte| format!("{:02X}", byte)).collect())
+                .unwrap_or_else(|| value.to_owned()),
+        }
+    }
+}
+
+impl Occurrence {
+    /// Creates the occurrence policy or aggregate called `name`, or returns `None` if it is not one
+    pub fn new(name: &str, arguments: &[String]) -> Option<Result<Occurrence, String>> {
+        let occurrence = match (name, arguments) {
+            ("first", []) => Occurrence::First,
+            ("last", []) => Occurrence::Last,
+            ("join", [separator]) => Occurrence::Join(separator.to_owned()),
+            ("join", []) => Occurrence::Join(" ".to_owned()),
+            ("concat", []) => Occurrence::Join(String::new()),
+            ("count", []) => Occurrence::Count,
+            ("sum", []) => Occurrence::Sum,
+            ("min", []) => Occurrence::Min,
+            ("max", []) => Occurrence::Max,
+            ("json", []) => Occurrence::Json,
+            ("first" | "last" | "concat" | "count" | "sum" | "min" | "max" | "json", _) => {
+                return Some(Err(format!("The function {}() takes no arguments after the value", name)))
+            }
+            ("join", _) => return Some(Err("The function join() takes a separator after the value".to_owned())),
+            _ => return None,
+        };
+        Some(Ok(occurrence))
+    }
+
+    /// Combines the values found, or returns `None` if there is no value to output
+    pub fn apply(&self, values: &[&str]) -> Option<String> {
+        match self {
+            Occurrence::First => values.first().map(|value| (*value).to_owned()),
+            Occurrence::Last => values.last().map(|value| (*value).to_owned()),
+            Occurrence::Join(_) if values.is_empty() => None,
+            Occurrence::Join(separator) => Some(values.join(separator)),
+            Occurrence::Count => Some(values.len().to_string()),
+            Occurrence::Sum => {
+                // values that are not numbers are left out, and integers are summed without rounding
+                let numbers: Vec<&str> = values
+                    .iter()
+                    .map(|value| value.trim())
+                    .filter(|value| value.parse::<f64>().is_ok())
+                    .collect();
+                let integers: Option<Vec<i128>> = numbers.iter().map(|value| value.parse().ok()).collect();
+                match integers {
+                    _ if numbers.is_empty() => None,
+                    Some(integers) => Some(integers.iter().sum::<i128>().to_string()),
+                    None => Some(numbers.iter().filter_map(|value| value.parse::<f64>().ok()).sum::<f64>().to_string()),
+                }
+            }
+            Occurrence::Min => values.iter().min_by(|value, other| ordering(value, other)).map(|value| (*value).to_owned()),
+            Occurrence::Max => values.iter().max_by(|value, other| ordering(value, other)).map(|value| (*value).to_owned()),
+            Occurrence::Json => Some(format!(
+                "[{}]",
+                values.iter().map(|value| json_string(value)).collect::<Vec<_>>().join(",")
+            )),
+        }
+    }
+}
+
+/// Quotes and escapes a value as a JSON string
+pub fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for character in value.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            character if character.is_control() => escaped.push_str(&format!("\\u{:04x}", character as u32)),
+            character => escaped.push(character),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+impl Explosion {
+    /// Creates the explosion from the arguments following the value: the delimiter, which defaults
+    /// to whitespace, and the number of parts in each value, which defaults to 1
+    pub fn new(arguments: &[String]) -> Result<Explosion, String> {
+        if arguments.len() > 2 {
+            return Err(format!(
+                "The function explode() takes 0, 1 or 2 argument(s) after the value, but {} were given",
+                arguments.len()
+            ));
+        }
+        let delimiter = arguments.first().filter(|delimiter| !delimiter.trim().is_empty()).cloned();
+        let group = match arguments.get(1) {
+            Some(group) => group
+                .parse::<usize>()
+                .ok()
+                .filter(|group| *group > 0)
+                .ok_or_else(|| format!("The function explode() expects a positive number, but got: {}", group))?,
+            None => 1,
+        };
+        Ok(Explosion { delimiter, group })
+    }
+
+    /// Splits the value, where parts that are grouped are joined with the delimiter again
+    pub fn split(&self, value: &str) -> Vec<String> {
+        let (parts, delimiter): (Vec<&str>, &str) = match &self.delimiter {
+            Some(delimiter) if value.is_empty() => (Vec::default(), delimiter),
+            Some(delimiter) => (value.split(delimiter.as_str()).collect(), delimiter),
+            None => (value.split_whitespace().collect(), " "),
+        };
+        parts.chunks(self.group).map(|group| group.join(delimiter)).collect()
+    }
+}
+
+impl Split {
+    /// Creates the split called `name`, for a value that is split into `columns` columns,
+    /// or returns `None` if it is not one
+    pub fn new(name: &str, arguments: &[String], columns: usize) -> Option<Result<Split, String>> {
+        let split = match (name, arguments) {
+            ("split", [delimiter]) if !delimiter.is_empty() => Ok(Split::Delimiter(delimiter.to_owned())),
+            ("split", _) => Err("The function split() takes a delimiter after the value".to_owned()),
+            ("capture", [pattern]) => match Regex::new(pattern) {
+                Ok(regex) if regex.captures_len() - 1 == columns => Ok(Split::Capture(regex)),
+                Ok(regex) => Err(format!(
+                    "The pattern of capture() has {} group(s), but there are {} columns",
+                    regex.captures_len() - 1,
+                    columns
+                )),
+                Err(error) => Err(format!("Invalid regular expression in capture(): {}", error)),
+            },
+            ("capture", _) => Err("The function capture() takes a regular expression after the value".to_owned()),
+            _ => return None,
+        };
+        Some(split)
+    }
+
+    /// The part at `index` of a value split into `columns` parts, where the last part
+    /// gets the remainder of the value when splitting on a delimiter
+    pub fn part(&self, value: &str, index: usize, columns: usize) -> String {
+        match self {
+            Split::Delimiter(delimiter) => value.splitn(columns, delimiter.as_str()).nth(index),
+            Split::Capture(regex) => regex
+                .captures(value)
+                .and_then(|groups| groups.get(index + 1))
+                .map(|group| group.as_str()),
+        }
+        .unwrap_or_default()
+        .to_owned()
+    }
+}
+
+/// Converts a 3GPP TS 32.298 timestamp, YYMMDDhhmmss followed by the sign and hhmm of the
+/// UTC offset, into ISO 8601. The sign is either a character or its hex code (2B or 2D).
+fn timestamp(value: &str, utc: bool) -> Option<String> {
+    let value = value.trim();
+    if !value.is_ascii() || value.len() < 17 {
+        return None;
+    }
+    let (sign, offset) = match &value[12..value.len() - 4] {
+        "+" | "2B" => (1, &value[value.len() - 4..]),
+        "-" | "2D" => (-1, &value[value.len() - 4..]),
+        _ => return None,
+    };
+    let digits = |text: &str| text.parse::<i64>().ok().filter(|_| text.bytes().all(|b| b.is_ascii_digit()));
+    let year = 2000 + digits(&value[0..2])?;
+    let month = digits(&value[2..4])?;
+    let day = digits(&value[4..6])?;
+    let hour = digits(&value[6..8])?;
+    let minute = digits(&value[8..10])?;
+    let second = digits(&value[10..12])?;
+    let offset_hours = digits(&offset[0..2])?;
+    let offset_minutes = digits(&offset[2..4])?;
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return None;
+    }
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    if !utc {
+        return Some(format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}{:02}:{:02}",
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            if sign < 0 { '-' } else { '+' },
+            offset_hours,
+            offset_minutes
+        ));
+    }
+    Some(utc_timestamp(
+        days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second
+            - sign * (offset_hours * 3600 + offset_minutes * 60),
+    ))
+}
+
+/// Formats seconds since 1970-01-01 as an ISO 8601 timestamp in UTC
+pub fn utc_timestamp(seconds: i64) -> String {
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let seconds = seconds.rem_euclid(86400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+/// Decodes hex encoded TBCD, where each byte holds two digits with the first digit in the
+/// low nibble, and F is used as filler. The nibbles A to E are the characters *, #, a, b and c.
+fn tbcd(value: &str) -> Option<String> {
+    let bytes = from_hex(value)?;
+    let mut digits = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        for nibble in [byte & 0x0F, byte >> 4] {
+            match nibble {
+                0..=9 => digits.push((b'0' + nibble) as char),
+                0x0A => digits.push('*'),
+                0x0B => digits.push('#'),
+                0x0C..=0x0E => digits.push((b'a' + nibble - 0x0C) as char),
+                _ => (),
+            }
+        }
+    }
+    Some(digits)
+}
+
+fn from_hex(value: &str) -> Option<Vec<u8>> {
+    let value = value.trim();
+    if !value.len().is_multiple_of(2) || !value.is_ascii() {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&value[index..index + 2], 16).ok())
+        .collect()
+}
+
+/// Decodes both the standard and the URL safe alphabet, with or without padding, skipping the
+/// line breaks and other whitespace that `xs:base64Binary` allows
+fn from_base64(value: &str) -> Option<Vec<u8>> {
+    let characters: Vec<u8> = value.bytes().filter(|character| !character.is_ascii_whitespace()).collect();
+    let padding = characters.iter().rev().take_while(|character| **character == b'=').count();
+    let mut bytes = Vec::with_capacity(characters.len() * 3 / 4);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for &character in &characters[..characters.len() - padding] {
+        let sextet = match character {
+            b'A'..=b'Z' => character - b'A',
+            b'a'..=b'z' => character - b'a' + 26,
+            b'0'..=b'9' => character - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return None,
+        };
+        buffer = buffer << 6 | sextet as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    let leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    match month {
+        2 if leap_year => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// The number of days since 1970-01-01 in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The inverse of `days_from_civil`
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Applies the transforms in order, the innermost function of the directive first
+pub fn apply_all(transforms: &[Transform], value: &str) -> String {
+    transforms
+        .iter()
+        .fold(value.to_owned(), |value, transform| transform.apply(&value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(name: &str, value: &str) -> String {
+        Transform::new(name, &[]).unwrap().apply(value)
+    }
+
+    fn aggregate(name: &str, values: &[&str]) -> Option<String> {
+        Occurrence::new(name, &[]).unwrap().unwrap().apply(values)
+    }
+
+    #[test]
+    fn occurrences_pick_or_combine_values() {
+        let values = ["b", "a", "c"];
+        assert_eq!(aggregate("first", &values).as_deref(), Some("b"));
+        assert_eq!(aggregate("last", &values).as_deref(), Some("c"));
+        assert_eq!(aggregate("join", &values).as_deref(), Some("b a c"));
+        assert_eq!(aggregate("concat", &values).as_deref(), Some("bac"));
+        assert_eq!(aggregate("count", &values).as_deref(), Some("3"));
+        assert_eq!(aggregate("json", &["a\"b", "c"]).as_deref(), Some(r#"["a\"b","c"]"#));
+    }
+
+    #[test]
+    fn occurrences_without_values() {
+        for name in ["first", "last", "join", "sum", "min", "max"] {
+            assert_eq!(aggregate(name, &[]), None, "{}()", name);
+        }
+        assert_eq!(aggregate("count", &[]).as_deref(), Some("0"));
+        assert_eq!(aggregate("json", &[]).as_deref(), Some("[]"));
+    }
+
+    #[test]
+    fn sums_are_integers_unless_a_value_has_decimals() {
+        assert_eq!(aggregate("sum", &["1", " 2 ", "x"]).as_deref(), Some("3"));
+        assert_eq!(aggregate("sum", &["170141183460469231731687303715884105727", "0"]).as_deref(), Some("170141183460469231731687303715884105727"));
+        assert_eq!(aggregate("sum", &["1", "2.5"]).as_deref(), Some("3.5"));
+        assert_eq!(aggregate("sum", &["x", ""]), None);
+    }
+
+    #[test]
+    fn min_and_max_compare_numbers_numerically() {
+        assert_eq!(aggregate("min", &["10", "9", "100"]).as_deref(), Some("9"));
+        assert_eq!(aggregate("max", &["10", "9", "100"]).as_deref(), Some("100"));
+        assert_eq!(aggregate("min", &["b", "a", "c"]).as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn min_and_max_of_mixed_values_do_not_depend_on_their_order() {
+        let orders = [["10", "9", "a"], ["a", "10", "9"], ["9", "a", "10"]];
+        for values in orders {
+            assert_eq!(aggregate("min", &values).as_deref(), Some("a"), "{:?}", values);
+            assert_eq!(aggregate("max", &values).as_deref(), Some("10"), "{:?}", values);
+        }
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    fn explode(arguments: &[&str], value: &str) -> Vec<String> {
+        Explosion::new(&strings(arguments)).unwrap().split(value)
+    }
+
+    #[test]
+    fn explosions_split_on_whitespace_by_default() {
+        assert_eq!(explode(&[], " 1 2\n 3 "), ["1", "2", "3"]);
+        assert_eq!(explode(&[" "], "1  2"), ["1", "2"]);
+        assert!(explode(&[], "").is_empty());
+    }
+
+    #[test]
+    fn explosions_group_parts() {
+        assert_eq!(explode(&["", "3"], "1 2 3 4 5 6 7"), ["1 2 3", "4 5 6", "7"]);
+        assert_eq!(explode(&[",", "2"], "a,b,c,d"), ["a,b", "c,d"]);
+    }
+
+    #[test]
+    fn explosions_with_a_delimiter_keep_empty_parts() {
+        assert_eq!(explode(&[","], "a,,b,"), ["a", "", "b", ""]);
+        assert!(explode(&[","], "").is_empty());
+    }
+
+    #[test]
+    fn explosions_take_a_positive_group() {
+        assert!(Explosion::new(&strings(&[",", "0"])).is_err());
+        assert!(Explosion::new(&strings(&[",", "x"])).is_err());
+        assert!(Explosion::new(&strings(&[",", "1", "2"])).is_err());
+    }
+
+    fn parts(name: &str, argument: &str, columns: usize, value: &str) -> Vec<String> {
+        let split = Split::new(name, &strings(&[argument]), columns).unwrap().unwrap();
+        (0..columns).map(|index| split.part(value, index, columns)).collect()
+    }
+
+    #[test]
+    fn the_last_split_column_gets_the_remainder() {
+        assert_eq!(parts("split", "-", 2, "a-b-c"), ["a", "b-c"]);
+        assert_eq!(parts("split", "-", 3, "a-b-c"), ["a", "b", "c"]);
+        assert_eq!(parts("split", "::", 2, "a::b::c"), ["a", "b::c"]);
+    }
+
+    #[test]
+    fn split_columns_without_a_part_are_empty() {
+        assert_eq!(parts("split", "-", 3, "a-b"), ["a", "b", ""]);
+        assert_eq!(parts("split", "-", 2, ""), ["", ""]);
+    }
+
+    #[test]
+    fn captures_fill_the_columns_in_order() {
+        assert_eq!(parts("capture", r"^(\d+)_(\d+)$", 2, "12_34"), ["12", "34"]);
+        assert_eq!(parts("capture", r"^(\d+)(?:_(\d+))?$", 2, "12"), ["12", ""]);
+    }
+
+    #[test]
+    fn captures_that_do_not_match_are_empty() {
+        assert_eq!(parts("capture", r"^(\d+)_(\d+)$", 2, "12-34"), ["", ""]);
+    }
+
+    #[test]
+    fn splits_check_their_arguments() {
+        assert!(Split::new("split", &strings(&[""]), 2).unwrap().is_err());
+        assert!(Split::new("split", &[], 2).unwrap().is_err());
+        assert!(Split::new("capture", &strings(&["(a)"]), 2).unwrap().is_err());
+        assert!(Split::new("capture", &strings(&["(a"]), 1).unwrap().is_err());
+        assert!(Split::new("upper", &[], 2).is_none());
+    }
+
+    #[test]
+    fn tbcd_swaps_nibbles_and_drops_filler() {
+        assert_eq!(apply("tbcd", "21436587F9"), "123456789");
+        assert_eq!(apply("tbcd", "214365"), "123456");
+        assert_eq!(apply("tbcd", "BADC"), "*#ab");
+        assert_eq!(apply("tbcd", "2143658"), "2143658");
+        assert_eq!(apply("tbcd", "21G3"), "21G3");
+    }
+
+    #[test]
+    fn hex_decodes_text() {
+        assert_eq!(apply("hex", "53475731"), "SGW1");
+        assert_eq!(apply("hex", "736777"), "sgw");
+        assert_eq!(apply("hex", "5347573"), "5347573");
+        assert_eq!(apply("hex", "ZZ"), "ZZ");
+        // bytes that are not UTF-8 are left as they are
+        assert_eq!(apply("hex", "FF"), "FF");
+    }
+
+    #[test]
+    fn base64_decodes_both_alphabets_with_or_without_padding() {
+        assert_eq!(apply("base64", "U0dXMQ=="), "SGW1");
+        assert_eq!(apply("base64", "U0dXMQ"), "SGW1");
+        assert_eq!(apply("base64", "U0dX"), "SGW");
+        assert_eq!(apply("base64_hex", "-_8="), "FBFF");
+        assert_eq!(apply("base64_hex", "+/8="), "FBFF");
+        assert_eq!(apply("base64_hex", "IUNlh/k="), "21436587F9");
+        assert_eq!(apply("base64", "U0d*"), "U0d*");
+    }
+
+    #[test]
+    fn base64_skips_whitespace() {
+        assert_eq!(apply("base64", "U0dX\nMQ=="), "SGW1");
+        assert_eq!(apply("base64", " U0dX\r\n  MQ==\n"), "SGW1");
+        assert_eq!(apply("base64_hex", "IUNl\th/k=\n"), "21436587F9");
+    }
+
+    #[test]
+    fn timestamp_keeps_the_offset() {
+        assert_eq!(apply("timestamp", "2105110815332B0200"), "2021-05-11T08:15:33+02:00");
+        assert_eq!(apply("timestamp", "210511081533-0530"), "2021-05-11T08:15:33-05:30");
+    }
+
+    #[test]
+    fn timestamp_utc_applies_the_offset() {
+        assert_eq!(apply("timestamp_utc", "2105110815332B0200"), "2021-05-11T06:15:33Z");
+        assert_eq!(apply("timestamp_utc", "2101010015332B0200"), "2020-12-31T22:15:33Z");
+        assert_eq!(apply("timestamp_utc", "2112312315332D0100"), "2022-01-01T00:15:33Z");
+    }
+
+    #[test]
+    fn timestamp_checks_the_length_of_the_month() {
+        assert_eq!(apply("timestamp", "2002290815332B0200"), "2020-02-29T08:15:33+02:00");
+        for invalid in ["2102310815332B0200", "2102290815332B0200", "2104310815332B0200", "2113010815332B0200"] {
+            assert_eq!(apply("timestamp", invalid), invalid);
+            assert_eq!(apply("timestamp_utc", invalid), invalid);
+        }
+    }
+
+    #[test]
+    fn timestamp_leaves_other_values_unchanged() {
+        for invalid in ["", "2105110815332B", "2105110815332A0200", "21051108153X2B0200", "2105112415332B0200"] {
+            assert_eq!(apply("timestamp", invalid), invalid);
+        }
+    }
+
+    #[test]
+    fn days_in_month_follows_leap_years() {
+        assert_eq!(days_in_month(2021, 2), 28);
+        assert_eq!(days_in_month(2024, 2), 29);
+        assert_eq!(days_in_month(2100, 2), 28);
+        assert_eq!(days_in_month(2000, 2), 29);
+        assert_eq!(days_in_month(2021, 4), 30);
+        assert_eq!(days_in_month(2021, 12), 31);
+    }
+
+    #[test]
+    fn days_from_civil_and_back() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        for days in [-719468, -1, 0, 11016, 11017, 18758, 2932896] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+        assert_eq!(civil_from_days(18758), (2021, 5, 11));
+    }
+
+    #[test]
+    fn utc_timestamp_formats_seconds() {
+        assert_eq!(utc_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(utc_timestamp(-1), "1969-12-31T23:59:59Z");
+        assert_eq!(utc_timestamp(951782400), "2000-02-29T00:00:00Z");
+    }
+}